@@ -0,0 +1,347 @@
+use super::partfile::open_part;
+use super::store::ChunkStore;
+use super::{get_file_or_stdin, get_output_directory, ArgumentError, BinaryOptions, Errors};
+use fastcdc::v2020;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::io::{BufRead, Read, Write};
+
+/// chunk size bounds of content-defined chunking
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct CdcOptions {
+    pub min_size: u32,
+    pub avg_size: u32,
+    pub max_size: u32,
+}
+
+impl CdcOptions {
+    fn parse_size(s: Option<&str>, name: &str, min: u32, max: u32) -> Result<Option<u32>, Errors> {
+        let v = match s {
+            Some(v) => v.parse::<u32>().map_err(|e| {
+                Errors::Arg(ArgumentError::new(name, &format!("parse error: {:?}", e)))
+            })?,
+            None => return Ok(None),
+        };
+        if v < min || v > max {
+            return Err(Errors::Arg(ArgumentError::new(
+                name,
+                &format!("{} must be between {} and {}", name, min, max),
+            )));
+        }
+        Ok(Some(v))
+    }
+    /// min and max default to a quarter and four times of avg(64KiB by default)
+    pub fn from_arg_matches(matches: &clap::ArgMatches) -> Result<Self, Errors> {
+        let avg_size = Self::parse_size(
+            matches.value_of("cdc-avg"),
+            "avg",
+            v2020::AVERAGE_MIN,
+            v2020::AVERAGE_MAX,
+        )?
+        .unwrap_or(65536);
+        let min_size = Self::parse_size(
+            matches.value_of("cdc-min"),
+            "min",
+            v2020::MINIMUM_MIN,
+            v2020::MINIMUM_MAX,
+        )?
+        .unwrap_or_else(|| (avg_size / 4).clamp(v2020::MINIMUM_MIN, v2020::MINIMUM_MAX));
+        let max_size = Self::parse_size(
+            matches.value_of("cdc-max"),
+            "max",
+            v2020::MAXIMUM_MIN,
+            v2020::MAXIMUM_MAX,
+        )?
+        .unwrap_or_else(|| {
+            avg_size
+                .saturating_mul(4)
+                .clamp(v2020::MAXIMUM_MIN, v2020::MAXIMUM_MAX)
+        });
+        if min_size > avg_size || avg_size > max_size {
+            return Err(Errors::Arg(ArgumentError::new(
+                "cdc",
+                &format!(
+                    "chunk sizes must be min <= avg <= max(min={}, avg={}, max={})",
+                    min_size, avg_size, max_size
+                ),
+            )));
+        }
+        Ok(CdcOptions {
+            min_size,
+            avg_size,
+            max_size,
+        })
+    }
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub(crate) fn sha256_hex(data: &[u8]) -> String {
+    to_hex(&Sha256::digest(data))
+}
+
+pub(crate) fn sha256_file(path: &std::path::Path) -> Result<String, Errors> {
+    let mut f = std::fs::File::open(path)
+        .map_err(|e| Errors::from_io(&e, &format!("opening {:?}", path)))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 65536];
+    loop {
+        let bytesread = f
+            .read(&mut buf)
+            .map_err(|e| Errors::from_io(&e, &format!("reading {:?}", path)))?;
+        if bytesread == 0 {
+            break;
+        }
+        hasher.update(&buf[0..bytesread]);
+    }
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// collects chunk hashes from a manifest written by --manifest,
+/// or from every file in the directory if path is a directory.
+fn read_previous_hashes(path: &str) -> Result<HashSet<String>, Errors> {
+    let path = std::path::Path::new(path);
+    let mut hashes = HashSet::new();
+    if path.is_dir() {
+        for entry in
+            std::fs::read_dir(path).map_err(|e| Errors::from_io(&e, "reading directory"))?
+        {
+            let entry = entry.map_err(|e| Errors::from_io(&e, "reading directory"))?;
+            if entry.path().is_file() {
+                hashes.insert(sha256_file(&entry.path())?);
+            }
+        }
+        return Ok(hashes);
+    }
+    let f = std::fs::File::open(path).map_err(|e| Errors::from_io(&e, "opening manifest"))?;
+    for line in std::io::BufReader::new(f).lines() {
+        let line = line.map_err(|e| Errors::from_io(&e, "reading manifest"))?;
+        if let Some(hash) = line.split('\t').next().filter(|v| !v.is_empty()) {
+            hashes.insert(hash.to_owned());
+        }
+    }
+    Ok(hashes)
+}
+
+/// splits input at content-defined boundaries(FastCDC), so inserted or removed bytes
+/// change only the chunks around them.
+/// manifest has a line per chunk: "sha256\toffset\tlength\tfile name".
+pub(crate) fn split_binary_cdc(opts: &BinaryOptions, cdc: &CdcOptions) -> Result<(), Errors> {
+    // previous hashes are read first since the directory may be overwritten
+    let previous_hashes = match &opts.compare {
+        Some(v) => Some(read_previous_hashes(v)?),
+        None => None,
+    };
+    let input = get_file_or_stdin(&opts.input)?;
+    let mut store = match (&opts.store, &opts.index) {
+        (Some(store), Some(index)) => Some(ChunkStore::new(store, index, opts.is_sync)?),
+        _ => None,
+    };
+    let prefix = opts.prefix.clone().unwrap_or(String::from("x"));
+    let mut output_file_path = match store {
+        Some(_) => std::path::PathBuf::new(),
+        None => get_output_directory(&opts.output)?,
+    };
+    output_file_path.push(format!("{}.{}", prefix, ""));
+    let mut current_suffix = String::new();
+    let extra_suffix = opts.extra_suffix.clone().unwrap_or_default();
+    let mut manifest = match &opts.manifest {
+        Some(v) => Some(std::io::BufWriter::new(
+            std::fs::File::create(v).map_err(|e| Errors::from_io(&e, "creating manifest"))?,
+        )),
+        None => None,
+    };
+    let mut chunks = 0u64;
+    let mut unchanged_chunks = 0u64;
+    let mut unchanged_bytes = 0u64;
+    let mut totalbytes = 0u64;
+    let chunker = v2020::StreamCDC::new(input, cdc.min_size, cdc.avg_size, cdc.max_size);
+    for chunk in chunker {
+        let chunk = chunk
+            .map_err(|e| Errors::from_io(&std::io::Error::from(e), "reading from input file"))?;
+        let hash = sha256_hex(&chunk.data);
+        if let Some(store) = &mut store {
+            store.write(&chunk.data)?;
+            store.finish_chunk()?;
+        } else {
+            let mut output_file = open_part(
+                &mut current_suffix,
+                &prefix,
+                &mut output_file_path,
+                opts.is_numerical_suffix,
+                &extra_suffix,
+            )?;
+            output_file
+                .write_all(&chunk.data)
+                .map_err(|e| Errors::from_io(&e, "writing output file"))?;
+            output_file.commit(opts.is_sync)?;
+        }
+        if let Some(manifest) = &mut manifest {
+            let file_name = match store {
+                Some(_) => format!("{}/{}", &hash[0..2], &hash[2..]),
+                None => output_file_path
+                    .file_name()
+                    .map(|v| v.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+            };
+            writeln!(
+                manifest,
+                "{}\t{}\t{}\t{}",
+                hash, chunk.offset, chunk.length, file_name
+            )
+            .map_err(|e| Errors::from_io(&e, "writing manifest"))?;
+        }
+        if previous_hashes.as_ref().map(|v| v.contains(&hash)) == Some(true) {
+            unchanged_chunks += 1;
+            unchanged_bytes += chunk.length as u64;
+        }
+        chunks += 1;
+        totalbytes += chunk.length as u64;
+    }
+    if let Some(store) = store {
+        store.finish()?;
+    } else if chunks == 0 {
+        // same as fixed-size split, empty input makes an empty part
+        open_part(
+            &mut current_suffix,
+            &prefix,
+            &mut output_file_path,
+            opts.is_numerical_suffix,
+            &extra_suffix,
+        )?
+        .commit(opts.is_sync)?;
+    }
+    if let Some(manifest) = &mut manifest {
+        manifest
+            .flush()
+            .map_err(|e| Errors::from_io(&e, "writing manifest"))?;
+    }
+    if previous_hashes.is_some() {
+        eprintln!(
+            "{} of {} chunks unchanged({} of {} bytes)",
+            unchanged_chunks, chunks, unchanged_bytes, totalbytes
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestDir;
+
+    const CDC: CdcOptions = CdcOptions {
+        min_size: 1024,
+        avg_size: 4096,
+        max_size: 16384,
+    };
+
+    /// pseudo random data, so chunk boundaries are not at every min-size
+    fn test_data(length: usize, seed: u64) -> Vec<u8> {
+        let mut x = seed;
+        (0..length)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                (x >> 24) as u8
+            })
+            .collect()
+    }
+
+    /// splits data with cdc, returns manifest lines and output folder
+    fn split(dir: &TestDir, name: &str, data: &[u8]) -> (Vec<String>, std::path::PathBuf) {
+        let input = dir.join(&format!("{}.bin", name));
+        let output = dir.join(name);
+        let manifest = dir.join(&format!("{}.manifest", name));
+        std::fs::write(&input, data).unwrap();
+        std::fs::create_dir(&output).unwrap();
+        let opts = BinaryOptions::new(1)
+            .with_input(input.to_str())
+            .with_output(output.to_str())
+            .with_manifest(manifest.to_str())
+            .with_cdc(Some(CDC));
+        split_binary_cdc(&opts, &CDC).unwrap();
+        let lines = std::fs::read_to_string(&manifest)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect();
+        (lines, output)
+    }
+
+    fn hashes(lines: &[String]) -> Vec<&str> {
+        lines
+            .iter()
+            .map(|v| v.split('\t').next().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn cdc_round_trip() {
+        let dir = TestDir::new("cdc-round-trip");
+        let data = test_data(200000, 1);
+        let (lines, output) = split(&dir, "a", &data);
+        let mut combined = Vec::new();
+        let mut offset = 0u64;
+        for line in lines.iter() {
+            let columns: Vec<&str> = line.split('\t').collect();
+            let chunk = std::fs::read(output.join(columns[3])).unwrap();
+            let length = chunk.len() as u64;
+            assert!(length >= CDC.min_size as u64 || offset + length == data.len() as u64);
+            assert!(length <= CDC.max_size as u64);
+            assert_eq!(columns[0], sha256_hex(&chunk));
+            assert_eq!(columns[1], offset.to_string());
+            assert_eq!(columns[2], length.to_string());
+            offset += length;
+            combined.extend(chunk);
+        }
+        assert_eq!(combined, data);
+    }
+
+    #[test]
+    fn cdc_boundaries_after_insertion() {
+        let dir = TestDir::new("cdc-insertion");
+        let data = test_data(200000, 2);
+        let mut inserted = test_data(100, 3);
+        inserted.extend_from_slice(&data);
+        let (lines, _) = split(&dir, "a", &data);
+        let (inserted_lines, _) = split(&dir, "b", &inserted);
+        let inserted_hashes = hashes(&inserted_lines);
+        // only chunks around the inserted bytes are changed
+        let changed = hashes(&lines)
+            .iter()
+            .filter(|v| !inserted_hashes.contains(v))
+            .count();
+        assert!(lines.len() > 20);
+        assert!(
+            changed <= 2,
+            "{} of {} chunks changed",
+            changed,
+            lines.len()
+        );
+        let tail: Vec<&str> = hashes(&lines).into_iter().skip(2).collect();
+        assert!(hashes(&inserted_lines).ends_with(&tail));
+    }
+
+    #[test]
+    fn cdc_store_round_trip() {
+        let dir = TestDir::new("cdc-store");
+        let data = test_data(100000, 4);
+        let input = dir.join("in");
+        std::fs::write(&input, &data).unwrap();
+        let index = dir.join("index").to_string_lossy().into_owned();
+        let store = dir.join("store").to_string_lossy().into_owned();
+        let opts = BinaryOptions::new(1)
+            .with_input(input.to_str())
+            .with_store(Some(&store))
+            .with_index(Some(&index))
+            .with_cdc(Some(CDC));
+        split_binary_cdc(&opts, &CDC).unwrap();
+        let mut output = Vec::new();
+        crate::store::combine_from_store(&index, &store, &mut output, &mut [0u8; 1000]).unwrap();
+        assert_eq!(output, data);
+    }
+}
@@ -0,0 +1,529 @@
+use super::{
+    create_extra_suffix_option, create_input_option, create_numeric_suffix_option,
+    create_output_option, create_prefix_option, get_file_or_stdin, get_output_directory,
+    get_text_codec, sanitize_file_name, split_line_ending, ArgumentError, Errors, LineOptions,
+    LineReader, PartitionWriter, TextPartWriter,
+};
+use clap::{App, Arg, SubCommand};
+use encoding_rs::Decoder;
+
+#[derive(Debug, Default)]
+pub struct CsvOptions {
+    pub max_records: u64,
+    pub max_bytes: Option<u64>,
+    pub input: Option<String>,
+    pub output: Option<String>,
+    pub prefix: Option<String>,
+    pub encoding: Option<String>,
+    pub is_numerical_suffix: bool,
+    pub extra_suffix: Option<String>,
+    pub delimiter: char,
+    pub quote: char,
+    pub escape: Option<char>,
+    pub has_header: bool,
+    pub is_validate: bool,
+    pub partition_by: Option<String>,
+    pub max_open_files: usize,
+}
+
+impl CsvOptions {
+    pub fn new(max_records: u64) -> Self {
+        CsvOptions {
+            max_records,
+            delimiter: ',',
+            quote: '"',
+            has_header: true,
+            ..Default::default()
+        }
+    }
+    pub fn with_max_bytes(mut self, max_bytes: Option<u64>) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+    pub fn with_input(mut self, s: Option<&str>) -> Self {
+        self.input = s.map(String::from);
+        self
+    }
+    pub fn with_output(mut self, s: Option<&str>) -> Self {
+        self.output = s.map(String::from);
+        self
+    }
+    pub fn with_prefix(mut self, s: Option<&str>) -> Self {
+        self.prefix = s.map(String::from);
+        self
+    }
+    pub fn with_encoding(mut self, s: Option<&str>) -> Self {
+        self.encoding = s.map(String::from);
+        self
+    }
+    pub fn with_is_numerical_suffix(mut self, b: bool) -> Self {
+        self.is_numerical_suffix = b;
+        self
+    }
+    pub fn with_extra_suffix(mut self, s: Option<&str>) -> Self {
+        self.extra_suffix = s.map(String::from);
+        self
+    }
+    pub fn with_delimiter(mut self, c: char) -> Self {
+        self.delimiter = c;
+        self
+    }
+    pub fn with_quote(mut self, c: char) -> Self {
+        self.quote = c;
+        self
+    }
+    pub fn with_escape(mut self, c: Option<char>) -> Self {
+        self.escape = c;
+        self
+    }
+    pub fn with_has_header(mut self, b: bool) -> Self {
+        self.has_header = b;
+        self
+    }
+    pub fn with_is_validate(mut self, b: bool) -> Self {
+        self.is_validate = b;
+        self
+    }
+    pub fn with_partition_by(mut self, s: Option<&str>) -> Self {
+        self.partition_by = s.map(String::from);
+        self
+    }
+    pub fn with_max_open_files(mut self, n: usize) -> Self {
+        self.max_open_files = n;
+        self
+    }
+    /// "\t" and "tab" are accepted for tab character
+    fn parse_char(s: &str, name: &str) -> Result<char, Errors> {
+        if s == "\\t" || s == "tab" {
+            return Ok('\t');
+        }
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Ok(c),
+            _ => Err(Errors::Arg(ArgumentError::new(
+                name,
+                &format!("must be a single character: {}", s),
+            ))),
+        }
+    }
+    pub fn from_arg_matches(matches: &clap::ArgMatches) -> Result<CsvOptions, Errors> {
+        let max_bytes = match matches.value_of("max-bytes") {
+            Some(v) => Some(LineOptions::parse_u64(v, "max-bytes")?),
+            None => None,
+        };
+        let max_records = match matches.value_of("max-records") {
+            Some(v) => LineOptions::parse_u64(v, "max-records")?,
+            None if max_bytes.is_some() || matches.is_present("partition-by") => u64::MAX,
+            None => {
+                return Err(Errors::Arg(ArgumentError::new(
+                    "max-records",
+                    "max-records is empty",
+                )))
+            }
+        };
+        let max_open_files = match matches.value_of("max-open-files") {
+            Some(v) => LineOptions::parse_u64(v, "max-open-files")? as usize,
+            None => 64,
+        };
+        let escape = match matches.value_of("escape") {
+            Some(v) => Some(Self::parse_char(v, "escape")?),
+            None => None,
+        };
+        Ok(Self::new(max_records)
+            .with_max_bytes(max_bytes)
+            .with_input(matches.value_of("input"))
+            .with_output(matches.value_of("output"))
+            .with_prefix(matches.value_of("prefix"))
+            .with_encoding(matches.value_of("encoding"))
+            .with_is_numerical_suffix(matches.is_present("numerical-suffix"))
+            .with_extra_suffix(matches.value_of("extra-suffix"))
+            .with_delimiter(Self::parse_char(
+                matches.value_of("delimiter").unwrap_or(","),
+                "delimiter",
+            )?)
+            .with_quote(Self::parse_char(
+                matches.value_of("quote").unwrap_or("\""),
+                "quote",
+            )?)
+            .with_escape(escape)
+            .with_has_header(!matches.is_present("no-header"))
+            .with_is_validate(matches.is_present("validate"))
+            .with_partition_by(matches.value_of("partition-by"))
+            .with_max_open_files(max_open_files))
+    }
+}
+
+#[derive(Debug)]
+pub struct CsvRecord {
+    pub raw: String,
+    pub fields: Vec<String>,
+    pub line_number: u64,
+    pub is_terminated: bool,
+}
+
+/// collects lines into RFC 4180 records, line endings in quoted fields belong to the field
+pub struct CsvRecordParser {
+    delimiter: char,
+    quote: char,
+    escape: Option<char>,
+    in_quotes: bool,
+    is_quote_closed: bool,
+    is_escaped: bool,
+    field: String,
+    fields: Vec<String>,
+    raw: String,
+    line_number: u64,
+    record_line_number: u64,
+}
+
+impl CsvRecordParser {
+    pub fn new(delimiter: char, quote: char, escape: Option<char>) -> Self {
+        CsvRecordParser {
+            delimiter,
+            quote,
+            // doubled quote is always handled as escaped quote
+            escape: escape.filter(|c| *c != quote),
+            in_quotes: false,
+            is_quote_closed: false,
+            is_escaped: false,
+            field: String::new(),
+            fields: Vec::new(),
+            raw: String::new(),
+            line_number: 0,
+            record_line_number: 1,
+        }
+    }
+    fn push_char(&mut self, c: char) {
+        if self.is_escaped {
+            self.field.push(c);
+            self.is_escaped = false;
+        } else if self.in_quotes {
+            if Some(c) == self.escape {
+                self.is_escaped = true;
+            } else if c == self.quote {
+                self.in_quotes = false;
+                self.is_quote_closed = true;
+            } else {
+                self.field.push(c);
+            }
+        } else if c == self.quote && self.is_quote_closed {
+            self.field.push(c);
+            self.in_quotes = true;
+            self.is_quote_closed = false;
+        } else if c == self.quote && self.field.is_empty() {
+            self.in_quotes = true;
+        } else if c == self.delimiter {
+            self.fields.push(std::mem::take(&mut self.field));
+            self.is_quote_closed = false;
+        } else {
+            self.field.push(c);
+            self.is_quote_closed = false;
+        }
+    }
+    fn take_record(&mut self, is_terminated: bool) -> CsvRecord {
+        self.fields.push(std::mem::take(&mut self.field));
+        let record = CsvRecord {
+            raw: std::mem::take(&mut self.raw),
+            fields: std::mem::take(&mut self.fields),
+            line_number: self.record_line_number,
+            is_terminated,
+        };
+        self.in_quotes = false;
+        self.is_quote_closed = false;
+        self.is_escaped = false;
+        self.record_line_number = self.line_number + 1;
+        record
+    }
+    /// returns a record when the line completes it
+    pub fn push_line(&mut self, line: &str) -> Option<CsvRecord> {
+        self.line_number += 1;
+        self.raw.push_str(line);
+        let (content, line_ending) = split_line_ending(line);
+        for c in content.chars() {
+            self.push_char(c);
+        }
+        if line_ending.is_empty() {
+            // incomplete line, only at the end of input
+            return None;
+        }
+        if self.in_quotes || self.is_escaped {
+            self.field.push_str(line_ending);
+            self.is_escaped = false;
+            return None;
+        }
+        Some(self.take_record(true))
+    }
+    /// returns the rest of input as a record which may be unterminated
+    pub fn finish(&mut self) -> Option<CsvRecord> {
+        if self.raw.is_empty() {
+            return None;
+        }
+        let is_terminated = !self.in_quotes && !self.is_escaped;
+        Some(self.take_record(is_terminated))
+    }
+}
+
+/// checks that every record has the same number of fields as the first one
+pub fn validate_record(record: &CsvRecord, column_count: &mut Option<usize>) -> Result<(), Errors> {
+    if !record.is_terminated {
+        return Err(Errors::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "unterminated quoted field in record at line {}",
+                record.line_number
+            ),
+        )));
+    }
+    match column_count {
+        Some(v) if *v != record.fields.len() => Err(Errors::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "record at line {} has {} columns, expected {}",
+                record.line_number,
+                record.fields.len(),
+                v
+            ),
+        ))),
+        Some(_) => Ok(()),
+        None => {
+            *column_count = Some(record.fields.len());
+            Ok(())
+        }
+    }
+}
+
+/// reads records from input and calls f with each record and whether it is the header
+fn read_records<F>(opts: &CsvOptions, decoder: Decoder, mut f: F) -> Result<(), Errors>
+where
+    F: FnMut(CsvRecord, bool) -> Result<(), Errors>,
+{
+    let input = get_file_or_stdin(&opts.input)?;
+    // line endings are a part of records, so CR is kept
+    let mut reader = LineReader::new(input, decoder).with_cr_kept(true);
+    let mut parser = CsvRecordParser::new(opts.delimiter, opts.quote, opts.escape);
+    let mut column_count: Option<usize> = None;
+    let mut is_header_read = !opts.has_header;
+    let mut records = Vec::new();
+    loop {
+        match reader.read_lines()? {
+            Some(lines) => {
+                for (line, _) in lines {
+                    records.extend(parser.push_line(&line));
+                }
+            }
+            None => match parser.finish() {
+                Some(record) => records.push(record),
+                None => break,
+            },
+        }
+        for record in records.drain(..) {
+            if opts.is_validate {
+                validate_record(&record, &mut column_count)?;
+            }
+            f(record, !is_header_read)?;
+            is_header_read = true;
+        }
+    }
+    Ok(())
+}
+
+pub fn split_csv(opts: &CsvOptions) -> Result<(), Errors> {
+    if opts.partition_by.is_some() {
+        return partition_csv(opts);
+    }
+    let (decoder, encoder) = get_text_codec(&opts.encoding)?;
+    let output_directory = get_output_directory(&opts.output)?;
+    let mut writer = TextPartWriter::new(
+        output_directory,
+        opts.prefix.as_deref(),
+        opts.extra_suffix.as_deref(),
+        opts.is_numerical_suffix,
+        encoder,
+        opts.max_records,
+        opts.max_bytes,
+    );
+    read_records(opts, decoder, |record, is_header| {
+        if is_header {
+            writer.write_header(&record.raw)
+        } else {
+            writer.write_record(&record.raw, record.is_terminated)
+        }
+    })?;
+    writer.finish()
+}
+
+/// routes every record to the file named after the value of partition column
+fn partition_csv(opts: &CsvOptions) -> Result<(), Errors> {
+    let column = opts.partition_by.clone().unwrap_or_default();
+    let (decoder, encoder) = get_text_codec(&opts.encoding)?;
+    let column_name = sanitize_file_name(&column);
+    let mut writer = PartitionWriter::new(
+        get_output_directory(&opts.output)?,
+        opts.prefix.as_deref(),
+        opts.extra_suffix.as_deref(),
+        encoder,
+        opts.max_open_files,
+    );
+    // 1-based column number is used when column name is not found in header
+    let mut column_index = match column.parse::<usize>() {
+        Ok(v) if v > 0 && !opts.has_header => Some(v - 1),
+        _ => None,
+    };
+    read_records(opts, decoder, |record, is_header| {
+        if is_header {
+            column_index = record.fields.iter().position(|v| *v == column).or_else(|| {
+                column
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|v| *v > 0)
+                    .map(|v| v - 1)
+            });
+            return writer.write_header(&record.raw);
+        }
+        let index = match column_index {
+            Some(v) => v,
+            None => {
+                return Err(Errors::Arg(ArgumentError::new(
+                    "partition-by",
+                    &format!("column not found: {}", column),
+                )))
+            }
+        };
+        let value = record.fields.get(index).map(|v| v.as_str()).unwrap_or("");
+        let key = format!("{}={}", column_name, sanitize_file_name(value));
+        writer.write_record(&key, &record.raw)
+    })?;
+    writer.finish()
+}
+
+pub fn create_csv_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("csv")
+        .about("split csv by records, quoted line breaks are kept in the record")
+        .arg(
+            Arg::with_name("max-records")
+                .required_unless_one(&["max-bytes", "partition-by"])
+                .help("max record number per file(header is not counted)"),
+        )
+        .arg(
+            Arg::with_name("max-bytes")
+                .long("max-bytes")
+                .takes_value(true)
+                .help("max encoded bytes per file, records are never cut(used with max-records, whichever is reached first)"),
+        )
+        .arg(
+            Arg::with_name("delimiter")
+                .short("d")
+                .long("delimiter")
+                .takes_value(true)
+                .help("field delimiter(\"\\t\" or \"tab\" for tab, default: ',')"),
+        )
+        .arg(
+            Arg::with_name("quote")
+                .long("quote")
+                .takes_value(true)
+                .help("quote character(default: '\"')"),
+        )
+        .arg(
+            Arg::with_name("escape")
+                .long("escape")
+                .takes_value(true)
+                .help("escape character in quoted field(default: none, doubled quote is always an escaped quote)"),
+        )
+        .arg(
+            Arg::with_name("no-header")
+                .long("no-header")
+                .takes_value(false)
+                .help("first record is not a header(default: header is repeated in every file)"),
+        )
+        .arg(
+            Arg::with_name("validate")
+                .long("validate")
+                .takes_value(false)
+                .help("fail when a record has different column count from the first record"),
+        )
+        .arg(
+            Arg::with_name("partition-by")
+                .long("partition-by")
+                .takes_value(true)
+                .conflicts_with_all(&["max-records", "max-bytes"])
+                .help("write records to files named after the value of the column(column name, or 1-based number); file name is '{prefix}{column}={value}{extra-suffix}', values differing only in case are written to the file of the first one"),
+        )
+        .arg(
+            Arg::with_name("max-open-files")
+                .long("max-open-files")
+                .takes_value(true)
+                .requires("partition-by")
+                .help("max number of files kept open with partition-by(default: 64)"),
+        )
+        .arg(
+            Arg::with_name("encoding")
+                .short("e")
+                .long("encoding")
+                .takes_value(true)
+                .help("input text encoding(default: utf-8)"),
+        )
+        .arg(create_input_option())
+        .arg(create_output_option())
+        .arg(create_prefix_option())
+        .arg(create_numeric_suffix_option())
+        .arg(create_extra_suffix_option())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(parser: &mut CsvRecordParser, lines: &[&str]) -> Vec<CsvRecord> {
+        let mut records: Vec<CsvRecord> =
+            lines.iter().filter_map(|v| parser.push_line(v)).collect();
+        records.extend(parser.finish());
+        records
+    }
+
+    #[test]
+    fn quoted_crlf() {
+        let mut parser = CsvRecordParser::new(',', '"', None);
+        let records = parse(&mut parser, &["a,\"b\r\n", "c\",d\r\n", "e,f\r\n"]);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].fields, vec!["a", "b\r\nc", "d"]);
+        assert_eq!(records[0].raw, "a,\"b\r\nc\",d\r\n");
+        assert_eq!(records[0].line_number, 1);
+        assert_eq!(records[1].fields, vec!["e", "f"]);
+        assert_eq!(records[1].line_number, 3);
+        assert!(records.iter().all(|v| v.is_terminated));
+    }
+
+    #[test]
+    fn doubled_quotes() {
+        let mut parser = CsvRecordParser::new(',', '"', None);
+        let records = parse(&mut parser, &["\"a\"\"b\",\"\"\"\",\"\"\n"]);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].fields, vec!["a\"b", "\"", ""]);
+    }
+
+    #[test]
+    fn escape_character() {
+        let mut parser = CsvRecordParser::new(';', '"', Some('\\'));
+        let records = parse(&mut parser, &["\"a\\\"b\";c\n"]);
+        assert_eq!(records[0].fields, vec!["a\"b", "c"]);
+    }
+
+    #[test]
+    fn unterminated_quote() {
+        let mut parser = CsvRecordParser::new(',', '"', None);
+        let records = parse(&mut parser, &["a,\"b\n", "c\n"]);
+        assert_eq!(records.len(), 1);
+        assert!(!records[0].is_terminated);
+        assert_eq!(records[0].raw, "a,\"b\nc\n");
+        assert!(validate_record(&records[0], &mut None).is_err());
+    }
+
+    #[test]
+    fn last_line_without_line_ending() {
+        let mut parser = CsvRecordParser::new(',', '"', None);
+        let records = parse(&mut parser, &["a,b\n", "c,d"]);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].fields, vec!["c", "d"]);
+        assert!(records[1].is_terminated);
+    }
+}
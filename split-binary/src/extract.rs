@@ -0,0 +1,184 @@
+use super::partfile::open_part;
+use super::{
+    check_partial_record, create_input_option, get_next_suffix, get_output_directory,
+    get_stdout_or_file, ArgumentError, BinaryOptions, Errors, DEFAULT_BUFFER_SIZE,
+};
+use clap::{App, Arg, SubCommand};
+use std::io::{Read, Seek, Write};
+
+/// opens input positioned at offset, file input is seeked and stdin is read through.
+/// also returns input length if it is known.
+pub(crate) fn open_input_at(
+    filepath: &Option<String>,
+    offset: u64,
+) -> Result<(Box<dyn Read>, Option<u64>), Errors> {
+    match filepath {
+        Some(filepath) => {
+            let mut f = std::fs::File::open(filepath)
+                .map_err(|e| Errors::from_io(&e, "opening input file"))?;
+            let length = f.metadata().ok().filter(|v| v.is_file()).map(|v| v.len());
+            f.seek(std::io::SeekFrom::Start(offset))
+                .map_err(|e| Errors::from_io(&e, "seeking input file"))?;
+            Ok((Box::new(f), length))
+        }
+        None => {
+            let mut input = std::io::stdin();
+            std::io::copy(&mut (&mut input).take(offset), &mut std::io::sink())
+                .map_err(|e| Errors::from_io(&e, "reading from input file"))?;
+            Ok((Box::new(input), None))
+        }
+    }
+}
+
+fn copy_range<W: Write>(
+    input: &mut dyn Read,
+    length: u64,
+    output: &mut W,
+    buffer_size: usize,
+) -> Result<u64, Errors> {
+    let mut input = input.take(length);
+    let mut buf = vec![0u8; buffer_size];
+    let mut copied = 0u64;
+    loop {
+        let bytesread = input
+            .read(&mut buf)
+            .map_err(|e| Errors::from_io(&e, "reading from input file"))?;
+        if bytesread == 0 {
+            break;
+        }
+        output
+            .write_all(&buf[0..bytesread])
+            .map_err(|e| Errors::from_io(&e, "writing output file"))?;
+        copied += bytesread as u64;
+    }
+    Ok(copied)
+}
+
+/// writes only the part of index `only`(0-based), with the same name as in full split
+pub(crate) fn split_binary_only(opts: &BinaryOptions, only: u64) -> Result<(), Errors> {
+    let offset = only
+        .checked_mul(opts.max_size)
+        .ok_or_else(|| Errors::Arg(ArgumentError::new("only", "part offset is too large")))?;
+    let (mut input, length) = open_input_at(&opts.input, offset)?;
+    if let Some(length) = length {
+        // an empty input still has the first part
+        if offset >= length && only > 0 {
+            return Err(Errors::Arg(ArgumentError::new(
+                "only",
+                &format!(
+                    "part {} does not exist, input has {} parts",
+                    only,
+                    std::cmp::max(length.div_ceil(std::cmp::max(opts.max_size, 1)), 1)
+                ),
+            )));
+        }
+    }
+    let mut current_suffix = String::from(if opts.is_numerical_suffix { "0" } else { "aa" });
+    for _ in 0..only {
+        current_suffix = get_next_suffix(&current_suffix, opts.is_numerical_suffix);
+    }
+    let prefix = opts.prefix.clone().unwrap_or(String::from("x"));
+    let mut output_file_path = get_output_directory(&opts.output)?;
+    output_file_path.push(format!("{}.{}", prefix, ""));
+    let mut output_file = open_part(
+        &mut current_suffix,
+        &prefix,
+        &mut output_file_path,
+        opts.is_numerical_suffix,
+        &opts.extra_suffix.clone().unwrap_or_default(),
+    )?;
+    let copied = copy_range(
+        &mut input,
+        opts.max_size,
+        &mut output_file,
+        opts.buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE),
+    )?;
+    if copied == 0 && only > 0 {
+        // the empty part is removed by dropping it
+        return Err(Errors::Arg(ArgumentError::new(
+            "only",
+            &format!("part {} does not exist", only),
+        )));
+    }
+    if length.is_none() && opts.record_size.is_some() {
+        // length of stdin is known only after reading the rest, regular file is checked before
+        let rest = std::io::copy(&mut input, &mut std::io::sink())
+            .map_err(|e| Errors::from_io(&e, "reading from input file"))?;
+        check_partial_record(opts, offset + copied + rest)?;
+    }
+    output_file.commit(opts.is_sync)
+}
+
+#[derive(Debug, Default)]
+pub struct ExtractOptions {
+    pub offset: u64,
+    pub length: Option<u64>,
+    pub input: Option<String>,
+    pub output: Option<String>,
+}
+
+impl ExtractOptions {
+    fn parse_u64(s: &str, name: &str) -> Result<u64, Errors> {
+        match s.parse::<u64>() {
+            Ok(v) => Ok(v),
+            Err(e) => Err(Errors::Arg(ArgumentError::new(
+                name,
+                &format!("parse error: {:?}", e),
+            ))),
+        }
+    }
+    pub fn from_arg_matches(matches: &clap::ArgMatches) -> Result<Self, Errors> {
+        Ok(ExtractOptions {
+            offset: match matches.value_of("offset") {
+                Some(v) => Self::parse_u64(v, "offset")?,
+                None => 0,
+            },
+            length: match matches.value_of("length") {
+                Some(v) => Some(Self::parse_u64(v, "length")?),
+                None => None,
+            },
+            input: matches.value_of("input").map(String::from),
+            output: matches.value_of("output").map(String::from),
+        })
+    }
+}
+
+/// writes a byte range of input to one output
+pub fn extract_range(opts: &ExtractOptions) -> Result<(), Errors> {
+    let (mut input, _) = open_input_at(&opts.input, opts.offset)?;
+    let mut output = get_stdout_or_file(&opts.output, false)?;
+    copy_range(
+        &mut input,
+        opts.length.unwrap_or(u64::MAX),
+        &mut output,
+        DEFAULT_BUFFER_SIZE,
+    )?;
+    output
+        .flush()
+        .map_err(|e| Errors::from_io(&e, "writing output file"))
+}
+
+pub fn create_extract_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("extract")
+        .about("extract a byte range")
+        .arg(
+            Arg::with_name("offset")
+                .long("offset")
+                .takes_value(true)
+                .help("start offset in bytes(default: 0)"),
+        )
+        .arg(
+            Arg::with_name("length")
+                .long("length")
+                .takes_value(true)
+                .help("length in bytes(default: until the end of input)"),
+        )
+        .arg(create_input_option())
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .help("output file path(default: stdout)"),
+        )
+}
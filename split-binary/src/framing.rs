@@ -0,0 +1,266 @@
+use super::partfile::{commit_buffered, open_part};
+use super::{
+    get_file_or_stdin, get_output_directory, ArgumentError, BinaryOptions, Errors,
+    DEFAULT_BUFFER_SIZE,
+};
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+/// encoding of the length prefix of frames, the length does not include the prefix itself
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Framing {
+    U16Le,
+    U16Be,
+    U32Le,
+    U32Be,
+    U64Le,
+    U64Be,
+    /// unsigned LEB128 like protocol buffers
+    Varint,
+}
+
+impl FromStr for Framing {
+    type Err = Errors;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "u16le" => Ok(Framing::U16Le),
+            "u16be" => Ok(Framing::U16Be),
+            "u32le" => Ok(Framing::U32Le),
+            "u32be" => Ok(Framing::U32Be),
+            "u64le" => Ok(Framing::U64Le),
+            "u64be" => Ok(Framing::U64Be),
+            "varint" => Ok(Framing::Varint),
+            _ => Err(Errors::Arg(ArgumentError::new(
+                "framing",
+                &format!("invalid framing:{}", s),
+            ))),
+        }
+    }
+}
+
+impl Framing {
+    pub const VALUES: &'static [&'static str] = &[
+        "u16le", "u16be", "u32le", "u32be", "u64le", "u64be", "varint",
+    ];
+}
+
+fn truncated_frame_error(offset: u64, message: &str) -> Errors {
+    Errors::Io(std::io::Error::new(
+        std::io::ErrorKind::UnexpectedEof,
+        format!("truncated frame at offset {}: {}", offset, message),
+    ))
+}
+
+/// reads length prefix into header and returns payload length,
+/// None if input ends just before the frame.
+fn read_frame_header<R: Read>(
+    input: &mut R,
+    framing: Framing,
+    header: &mut Vec<u8>,
+    offset: u64,
+) -> Result<Option<u64>, Errors> {
+    header.clear();
+    if let Framing::Varint = framing {
+        let mut length = 0u64;
+        loop {
+            let mut b = [0u8; 1];
+            let bytesread = input
+                .read(&mut b)
+                .map_err(|e| Errors::from_io(&e, "reading from input file"))?;
+            if bytesread == 0 {
+                if header.is_empty() {
+                    return Ok(None);
+                }
+                return Err(truncated_frame_error(offset, "incomplete varint length"));
+            }
+            let shift = 7 * header.len() as u32;
+            let value = (b[0] & 0x7f) as u64;
+            if shift >= 64 || (shift == 63 && value > 1) {
+                return Err(Errors::Io(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("varint length at offset {} is too large", offset),
+                )));
+            }
+            header.push(b[0]);
+            length |= value << shift;
+            if b[0] & 0x80 == 0 {
+                return Ok(Some(length));
+            }
+        }
+    }
+    let size = match framing {
+        Framing::U16Le | Framing::U16Be => 2,
+        Framing::U32Le | Framing::U32Be => 4,
+        _ => 8,
+    };
+    header.resize(size, 0);
+    let mut filled = 0;
+    while filled < size {
+        let bytesread = input
+            .read(&mut header[filled..])
+            .map_err(|e| Errors::from_io(&e, "reading from input file"))?;
+        if bytesread == 0 {
+            if filled == 0 {
+                return Ok(None);
+            }
+            return Err(truncated_frame_error(
+                offset,
+                &format!("only {} of {} length bytes", filled, size),
+            ));
+        }
+        filled += bytesread;
+    }
+    let mut bytes = [0u8; 8];
+    let length = match framing {
+        Framing::U16Le | Framing::U32Le | Framing::U64Le => {
+            bytes[..size].copy_from_slice(header);
+            u64::from_le_bytes(bytes)
+        }
+        _ => {
+            bytes[8 - size..].copy_from_slice(header);
+            u64::from_be_bytes(bytes)
+        }
+    };
+    Ok(Some(length))
+}
+
+/// splits length-prefixed frames, files are cut only between complete frames.
+/// a frame larger than max-size is written to its own file.
+pub(crate) fn split_binary_frames(opts: &BinaryOptions, framing: Framing) -> Result<(), Errors> {
+    let input = get_file_or_stdin(&opts.input)?;
+    let buffer_size = opts.buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE);
+    let mut input = std::io::BufReader::with_capacity(buffer_size, input);
+    let prefix = opts.prefix.clone().unwrap_or(String::from("x"));
+    let mut output_file_path = get_output_directory(&opts.output)?;
+    output_file_path.push(format!("{}.{}", prefix, ""));
+    let mut current_suffix = String::new();
+    let extra_suffix = opts.extra_suffix.clone().unwrap_or_default();
+    let mut output_file = std::io::BufWriter::with_capacity(
+        buffer_size,
+        open_part(
+            &mut current_suffix,
+            &prefix,
+            &mut output_file_path,
+            opts.is_numerical_suffix,
+            &extra_suffix,
+        )?,
+    );
+    let mut writtenbytes = 0u64;
+    let mut offset = 0u64;
+    let mut header = Vec::with_capacity(10);
+    while let Some(length) = read_frame_header(&mut input, framing, &mut header, offset)? {
+        let framesize = (header.len() as u64).saturating_add(length);
+        if writtenbytes > 0 && writtenbytes.saturating_add(framesize) > opts.max_size {
+            let next_output_file = std::io::BufWriter::with_capacity(
+                buffer_size,
+                open_part(
+                    &mut current_suffix,
+                    &prefix,
+                    &mut output_file_path,
+                    opts.is_numerical_suffix,
+                    &extra_suffix,
+                )?,
+            );
+            commit_buffered(
+                std::mem::replace(&mut output_file, next_output_file),
+                opts.is_sync,
+            )?;
+            writtenbytes = 0;
+            eprintln!("next suffix = {}", current_suffix);
+        }
+        if framesize > opts.max_size {
+            eprintln!(
+                "warning: a frame of {} bytes at offset {} is larger than max-size({})",
+                framesize, offset, opts.max_size
+            );
+        }
+        output_file
+            .write_all(&header)
+            .map_err(|e| Errors::from_io(&e, "writing output file"))?;
+        let copied = std::io::copy(&mut (&mut input).take(length), &mut output_file)
+            .map_err(|e| Errors::from_io(&e, "writing output file"))?;
+        if copied < length {
+            return Err(truncated_frame_error(
+                offset,
+                &format!("only {} of {} payload bytes", copied, length),
+            ));
+        }
+        writtenbytes += framesize;
+        offset += framesize;
+    }
+    commit_buffered(output_file, opts.is_sync)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(framing: Framing, data: &[u8]) -> (Result<Option<u64>, Errors>, Vec<u8>) {
+        let mut header = Vec::new();
+        let ret = read_frame_header(&mut &data[..], framing, &mut header, 0);
+        (ret, header)
+    }
+
+    fn error_kind(ret: Result<Option<u64>, Errors>) -> Option<std::io::ErrorKind> {
+        match ret {
+            Err(Errors::Io(e)) => Some(e.kind()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn fixed_size_lengths() {
+        let (ret, header) = read(Framing::U16Le, &[0x01, 0x02, 0xff]);
+        assert_eq!(ret.unwrap(), Some(0x0201));
+        assert_eq!(header, vec![0x01, 0x02]);
+        assert_eq!(read(Framing::U32Be, &[0, 0, 1, 0]).0.unwrap(), Some(256));
+        assert_eq!(
+            read(Framing::U64Le, &[1, 0, 0, 0, 0, 0, 0, 0]).0.unwrap(),
+            Some(1)
+        );
+        assert_eq!(read(Framing::U32Le, &[]).0.unwrap(), None);
+        assert_eq!(
+            error_kind(read(Framing::U32Le, &[1, 2]).0),
+            Some(std::io::ErrorKind::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn varint_lengths() {
+        let (ret, header) = read(Framing::Varint, &[0xac, 0x02, 0x00]);
+        assert_eq!(ret.unwrap(), Some(300));
+        assert_eq!(header, vec![0xac, 0x02]);
+        assert_eq!(read(Framing::Varint, &[0x00]).0.unwrap(), Some(0));
+        assert_eq!(read(Framing::Varint, &[]).0.unwrap(), None);
+        let max = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01];
+        assert_eq!(read(Framing::Varint, &max).0.unwrap(), Some(u64::MAX));
+    }
+
+    #[test]
+    fn truncated_varint() {
+        assert_eq!(
+            error_kind(read(Framing::Varint, &[0x80]).0),
+            Some(std::io::ErrorKind::UnexpectedEof)
+        );
+        assert_eq!(
+            error_kind(read(Framing::Varint, &[0xff, 0xff]).0),
+            Some(std::io::ErrorKind::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn too_large_varint() {
+        let overflow = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02];
+        assert_eq!(
+            error_kind(read(Framing::Varint, &overflow).0),
+            Some(std::io::ErrorKind::InvalidData)
+        );
+        let too_long = [
+            0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00,
+        ];
+        assert_eq!(
+            error_kind(read(Framing::Varint, &too_long).0),
+            Some(std::io::ErrorKind::InvalidData)
+        );
+    }
+}
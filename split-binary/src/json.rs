@@ -0,0 +1,817 @@
+use super::partfile::{commit_buffered, open_part};
+use super::{
+    create_extra_suffix_option, create_input_option, create_numeric_suffix_option,
+    create_output_option, create_prefix_option, encode_to_vec, get_file_or_stdin,
+    get_output_directory, get_text_codec, ArgumentError, Errors, LineOptions, LineReader,
+    TextPartWriter, DEFAULT_BUFFER_SIZE,
+};
+use clap::{App, Arg, SubCommand};
+use std::io::{Read, Write};
+
+#[derive(Debug, Default)]
+pub struct NdjsonOptions {
+    pub max_records: u64,
+    pub max_bytes: Option<u64>,
+    pub input: Option<String>,
+    pub output: Option<String>,
+    pub prefix: Option<String>,
+    pub encoding: Option<String>,
+    pub is_numerical_suffix: bool,
+    pub extra_suffix: Option<String>,
+    pub is_validate: bool,
+    pub is_compact: bool,
+    pub reject_file: Option<String>,
+}
+
+impl NdjsonOptions {
+    pub fn new(max_records: u64) -> Self {
+        NdjsonOptions {
+            max_records,
+            ..Default::default()
+        }
+    }
+    pub fn with_max_bytes(mut self, max_bytes: Option<u64>) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+    pub fn with_input(mut self, s: Option<&str>) -> Self {
+        self.input = s.map(String::from);
+        self
+    }
+    pub fn with_output(mut self, s: Option<&str>) -> Self {
+        self.output = s.map(String::from);
+        self
+    }
+    pub fn with_prefix(mut self, s: Option<&str>) -> Self {
+        self.prefix = s.map(String::from);
+        self
+    }
+    pub fn with_encoding(mut self, s: Option<&str>) -> Self {
+        self.encoding = s.map(String::from);
+        self
+    }
+    pub fn with_is_numerical_suffix(mut self, b: bool) -> Self {
+        self.is_numerical_suffix = b;
+        self
+    }
+    pub fn with_extra_suffix(mut self, s: Option<&str>) -> Self {
+        self.extra_suffix = s.map(String::from);
+        self
+    }
+    pub fn with_is_validate(mut self, b: bool) -> Self {
+        self.is_validate = b;
+        self
+    }
+    pub fn with_is_compact(mut self, b: bool) -> Self {
+        self.is_compact = b;
+        self
+    }
+    pub fn with_reject_file(mut self, s: Option<&str>) -> Self {
+        self.reject_file = s.map(String::from);
+        self
+    }
+    pub fn from_arg_matches(matches: &clap::ArgMatches) -> Result<NdjsonOptions, Errors> {
+        let max_bytes = match matches.value_of("max-bytes") {
+            Some(v) => Some(LineOptions::parse_u64(v, "max-bytes")?),
+            None => None,
+        };
+        let max_records = match matches.value_of("max-records") {
+            Some(v) => LineOptions::parse_u64(v, "max-records")?,
+            None if max_bytes.is_some() => u64::MAX,
+            None => {
+                return Err(Errors::Arg(ArgumentError::new(
+                    "max-records",
+                    "max-records is empty",
+                )))
+            }
+        };
+        Ok(Self::new(max_records)
+            .with_max_bytes(max_bytes)
+            .with_input(matches.value_of("input"))
+            .with_output(matches.value_of("output"))
+            .with_prefix(matches.value_of("prefix"))
+            .with_encoding(matches.value_of("encoding"))
+            .with_is_numerical_suffix(matches.is_present("numerical-suffix"))
+            .with_extra_suffix(matches.value_of("extra-suffix"))
+            .with_is_validate(matches.is_present("validate"))
+            .with_is_compact(matches.is_present("compact"))
+            .with_reject_file(matches.value_of("reject-file")))
+    }
+}
+
+#[derive(Debug)]
+pub struct JsonRecord {
+    pub raw: String,
+    pub line_number: u64,
+    pub is_complete: bool,
+}
+
+/// collects lines into json values by tracking brackets outside of strings,
+/// so pretty-printed values spanning multiple lines are one record.
+pub struct JsonRecordScanner {
+    depth: i64,
+    in_string: bool,
+    is_escaped: bool,
+    is_broken: bool,
+    raw: String,
+    line_number: u64,
+    record_line_number: u64,
+}
+
+impl JsonRecordScanner {
+    pub fn new() -> Self {
+        JsonRecordScanner {
+            depth: 0,
+            in_string: false,
+            is_escaped: false,
+            is_broken: false,
+            raw: String::new(),
+            line_number: 0,
+            record_line_number: 0,
+        }
+    }
+    fn take_record(&mut self) -> JsonRecord {
+        let record = JsonRecord {
+            raw: std::mem::take(&mut self.raw),
+            line_number: self.record_line_number,
+            is_complete: self.depth == 0 && !self.in_string && !self.is_broken,
+        };
+        self.depth = 0;
+        self.in_string = false;
+        self.is_escaped = false;
+        self.is_broken = false;
+        record
+    }
+    /// pushes records which are completed by the line
+    pub fn push_line(&mut self, line: &str, records: &mut Vec<JsonRecord>) {
+        self.line_number += 1;
+        if !self.raw.is_empty() && (line.starts_with('{') || line.starts_with('[')) {
+            // a value starting at column 0 while the open value is not closed yet
+            // means the open one is broken, so only its lines are taken as a record
+            records.push(self.take_record());
+        }
+        if self.raw.is_empty() {
+            if line.trim().is_empty() {
+                // blank lines between records are ignored
+                return;
+            }
+            self.record_line_number = self.line_number;
+        }
+        self.raw.push_str(line);
+        for c in line.chars() {
+            if self.in_string {
+                if self.is_escaped {
+                    self.is_escaped = false;
+                } else if c == '\\' {
+                    self.is_escaped = true;
+                } else if c == '"' {
+                    self.in_string = false;
+                }
+            } else {
+                match c {
+                    '"' => self.in_string = true,
+                    '{' | '[' => self.depth += 1,
+                    '}' | ']' => {
+                        self.depth -= 1;
+                        if self.depth < 0 {
+                            self.is_broken = true;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        if !line.ends_with('\n') && !line.ends_with('\r') {
+            // incomplete line, only at the end of input
+            return;
+        }
+        // strings cannot contain line breaks, so the record ends here anyway
+        if self.depth <= 0 || self.in_string {
+            records.push(self.take_record());
+        }
+    }
+    /// returns the rest of input as a record which may be incomplete
+    pub fn finish(&mut self) -> Option<JsonRecord> {
+        if self.raw.trim().is_empty() {
+            return None;
+        }
+        Some(self.take_record())
+    }
+}
+
+/// removes whitespaces outside of strings
+pub fn compact_json(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
+    let mut in_string = false;
+    let mut is_escaped = false;
+    for c in s.chars() {
+        if in_string {
+            if is_escaped {
+                is_escaped = false;
+            } else if c == '\\' {
+                is_escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+            ret.push(c);
+        } else if !c.is_whitespace() {
+            if c == '"' {
+                in_string = true;
+            }
+            ret.push(c);
+        }
+    }
+    ret
+}
+
+fn is_valid_json(s: &str) -> bool {
+    serde_json::from_str::<serde::de::IgnoredAny>(s).is_ok()
+}
+
+pub fn split_ndjson(opts: &NdjsonOptions) -> Result<(), Errors> {
+    let input = get_file_or_stdin(&opts.input)?;
+    let (decoder, encoder) = get_text_codec(&opts.encoding)?;
+    let output_directory = get_output_directory(&opts.output)?;
+    // line endings are a part of records, so CR is kept
+    let mut reader = LineReader::new(input, decoder).with_cr_kept(true);
+    let mut writer = TextPartWriter::new(
+        output_directory,
+        opts.prefix.as_deref(),
+        opts.extra_suffix.as_deref(),
+        opts.is_numerical_suffix,
+        encoder,
+        opts.max_records,
+        opts.max_bytes,
+    )
+    // a part is renamed to its name only after its last record is written
+    .with_temporary_parts(true);
+    let mut reject_file = match &opts.reject_file {
+        Some(v) => Some((
+            std::fs::File::create(v).map_err(|e| Errors::from_io(&e, "creating reject file"))?,
+            get_text_codec(&opts.encoding)?.1,
+        )),
+        None => None,
+    };
+    let mut scanner = JsonRecordScanner::new();
+    let mut records = Vec::new();
+    let mut wbuf: Vec<u8> = Vec::new();
+    let mut rejected = 0u64;
+    loop {
+        match reader.read_lines()? {
+            Some(lines) => {
+                for (line, _) in lines {
+                    scanner.push_line(&line, &mut records);
+                }
+            }
+            None => match scanner.finish() {
+                Some(record) => records.push(record),
+                None => break,
+            },
+        }
+        for record in records.drain(..) {
+            let is_valid = record.is_complete && (!opts.is_validate || is_valid_json(&record.raw));
+            if !is_valid {
+                match &mut reject_file {
+                    Some((f, encoder)) => {
+                        encode_to_vec(encoder, &record.raw, &mut wbuf);
+                        f.write_all(&wbuf)
+                            .map_err(|e| Errors::from_io(&e, "writing reject file"))?;
+                        wbuf.clear();
+                        rejected += 1;
+                        continue;
+                    }
+                    None => {
+                        return Err(Errors::Io(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!("invalid json record at line {}", record.line_number),
+                        )))
+                    }
+                }
+            }
+            if opts.is_compact {
+                let mut compacted = compact_json(&record.raw);
+                compacted.push('\n');
+                writer.write_record(&compacted, true)?;
+            } else {
+                writer.write_record(&record.raw, true)?;
+            }
+        }
+    }
+    if rejected != 0 {
+        eprintln!("{} invalid records were rejected", rejected);
+    }
+    writer.finish()
+}
+
+pub fn create_ndjson_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("ndjson")
+        .about("split json lines by records, pretty-printed values spanning lines are kept in one record")
+        .arg(
+            Arg::with_name("max-records")
+                .required_unless("max-bytes")
+                .help("max record number per file"),
+        )
+        .arg(
+            Arg::with_name("max-bytes")
+                .long("max-bytes")
+                .takes_value(true)
+                .help("max encoded bytes per file, records are never cut(used with max-records, whichever is reached first)"),
+        )
+        .arg(
+            Arg::with_name("validate")
+                .long("validate")
+                .takes_value(false)
+                .help("parse every record and treat records which are not valid json as invalid"),
+        )
+        .arg(
+            Arg::with_name("reject-file")
+                .long("reject-file")
+                .takes_value(true)
+                .help("write invalid records to this file instead of stopping with error, an unclosed record ends before the next line starting with { or ["),
+        )
+        .arg(
+            Arg::with_name("compact")
+                .long("compact")
+                .takes_value(false)
+                .help("write every record in one line by removing whitespaces"),
+        )
+        .arg(
+            Arg::with_name("encoding")
+                .short("e")
+                .long("encoding")
+                .takes_value(true)
+                .help("input text encoding(default: utf-8)"),
+        )
+        .arg(create_input_option())
+        .arg(create_output_option())
+        .arg(create_prefix_option())
+        .arg(create_numeric_suffix_option())
+        .arg(create_extra_suffix_option())
+}
+
+#[derive(Debug, Default)]
+pub struct JsonArrayOptions {
+    pub max_elements: u64,
+    pub max_bytes: Option<u64>,
+    pub input: Option<String>,
+    pub output: Option<String>,
+    pub prefix: Option<String>,
+    pub is_numerical_suffix: bool,
+    pub extra_suffix: Option<String>,
+}
+
+impl JsonArrayOptions {
+    pub fn new(max_elements: u64) -> Self {
+        JsonArrayOptions {
+            max_elements,
+            ..Default::default()
+        }
+    }
+    pub fn with_max_bytes(mut self, max_bytes: Option<u64>) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+    pub fn with_input(mut self, s: Option<&str>) -> Self {
+        self.input = s.map(String::from);
+        self
+    }
+    pub fn with_output(mut self, s: Option<&str>) -> Self {
+        self.output = s.map(String::from);
+        self
+    }
+    pub fn with_prefix(mut self, s: Option<&str>) -> Self {
+        self.prefix = s.map(String::from);
+        self
+    }
+    pub fn with_is_numerical_suffix(mut self, b: bool) -> Self {
+        self.is_numerical_suffix = b;
+        self
+    }
+    pub fn with_extra_suffix(mut self, s: Option<&str>) -> Self {
+        self.extra_suffix = s.map(String::from);
+        self
+    }
+    pub fn from_arg_matches(matches: &clap::ArgMatches) -> Result<JsonArrayOptions, Errors> {
+        let max_bytes = match matches.value_of("max-bytes") {
+            Some(v) => Some(LineOptions::parse_u64(v, "max-bytes")?),
+            None => None,
+        };
+        let max_elements = match matches.value_of("max-elements") {
+            Some(v) => match LineOptions::parse_u64(v, "max-elements")? {
+                0 => {
+                    return Err(Errors::Arg(ArgumentError::new(
+                        "max-elements",
+                        "max-elements must be larger than 0",
+                    )))
+                }
+                v => v,
+            },
+            None if max_bytes.is_some() => u64::MAX,
+            None => {
+                return Err(Errors::Arg(ArgumentError::new(
+                    "max-elements",
+                    "max-elements is empty",
+                )))
+            }
+        };
+        Ok(Self::new(max_elements)
+            .with_max_bytes(max_bytes)
+            .with_input(matches.value_of("input"))
+            .with_output(matches.value_of("output"))
+            .with_prefix(matches.value_of("prefix"))
+            .with_is_numerical_suffix(matches.is_present("numerical-suffix"))
+            .with_extra_suffix(matches.value_of("extra-suffix")))
+    }
+}
+
+fn invalid_json(offset: u64, description: &str) -> Errors {
+    Errors::Io(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("{} at byte {}", description, offset),
+    ))
+}
+
+#[derive(Debug, PartialEq)]
+enum ArrayScanState {
+    Before,
+    Elements,
+    After,
+}
+
+/// scans a top-level json array byte by byte and collects one element at a time
+struct JsonArrayScanner {
+    element: Vec<u8>,
+    state: ArrayScanState,
+    depth: u64,
+    in_string: bool,
+    is_escaped: bool,
+    /// whether the current element is complete and only a separator can follow
+    is_value_closed: bool,
+    is_after_comma: bool,
+    offset: u64,
+}
+
+impl JsonArrayScanner {
+    fn new() -> Self {
+        JsonArrayScanner {
+            element: Vec::new(),
+            state: ArrayScanState::Before,
+            depth: 0,
+            in_string: false,
+            is_escaped: false,
+            is_value_closed: false,
+            is_after_comma: false,
+            offset: 0,
+        }
+    }
+    /// returns true when the byte ends an element, which is in self.element
+    fn push(&mut self, b: u8) -> Result<bool, Errors> {
+        self.offset += 1;
+        let offset = self.offset;
+        let element = &mut self.element;
+        match self.state {
+            ArrayScanState::Before => match b {
+                b'[' => self.state = ArrayScanState::Elements,
+                0xef | 0xbb | 0xbf if offset <= 3 => (),
+                b if b.is_ascii_whitespace() => (),
+                _ => return Err(invalid_json(offset, "top-level value is not an array")),
+            },
+            ArrayScanState::After => {
+                if !b.is_ascii_whitespace() {
+                    return Err(invalid_json(offset, "unexpected data after array"));
+                }
+            }
+            ArrayScanState::Elements if self.in_string => {
+                element.push(b);
+                if self.is_escaped {
+                    self.is_escaped = false;
+                } else if b == b'\\' {
+                    self.is_escaped = true;
+                } else if b == b'"' {
+                    self.in_string = false;
+                    self.is_value_closed = self.depth == 0;
+                }
+            }
+            ArrayScanState::Elements => match b {
+                b',' if self.depth == 0 => {
+                    if element.is_empty() {
+                        return Err(invalid_json(offset, "empty array element"));
+                    }
+                    self.is_after_comma = true;
+                    return Ok(true);
+                }
+                b']' if self.depth == 0 => {
+                    if element.is_empty() && self.is_after_comma {
+                        return Err(invalid_json(offset, "trailing comma in array"));
+                    }
+                    self.state = ArrayScanState::After;
+                    return Ok(!element.is_empty());
+                }
+                b if b.is_ascii_whitespace() && (self.depth == 0 || element.is_empty()) => {
+                    // a scalar ends at whitespace
+                    self.is_value_closed = !element.is_empty();
+                }
+                b'}' | b']' if self.depth == 0 => {
+                    return Err(invalid_json(offset, "unbalanced bracket"));
+                }
+                // another value follows without comma
+                _ if self.depth == 0 && self.is_value_closed => {
+                    return Err(invalid_json(offset, "unexpected data after array element"));
+                }
+                b'{' | b'[' | b'"' if self.depth == 0 && !element.is_empty() => {
+                    return Err(invalid_json(offset, "unexpected data after array element"));
+                }
+                b'{' | b'[' => {
+                    self.depth += 1;
+                    element.push(b);
+                }
+                b'}' | b']' => {
+                    self.depth -= 1;
+                    element.push(b);
+                    self.is_value_closed = self.depth == 0;
+                }
+                b'"' => {
+                    self.in_string = true;
+                    element.push(b);
+                }
+                _ => element.push(b),
+            },
+        }
+        Ok(false)
+    }
+    /// clears the element after it is written
+    fn clear_element(&mut self) {
+        self.element.clear();
+        self.is_value_closed = false;
+    }
+    /// returns error if input ends before the array is closed
+    fn finish(&self) -> Result<(), Errors> {
+        if self.state != ArrayScanState::After {
+            return Err(invalid_json(self.offset, "unexpected end of input"));
+        }
+        Ok(())
+    }
+}
+
+/// splits a top-level json array into arrays of at most max_elements elements.
+/// input is scanned byte by byte and only one element is kept in memory.
+pub fn split_json_array(opts: &JsonArrayOptions) -> Result<(), Errors> {
+    let mut input = get_file_or_stdin(&opts.input)?;
+    let output_directory = get_output_directory(&opts.output)?;
+    let prefix = opts.prefix.clone().unwrap_or_else(|| String::from("x"));
+    let mut output_file_path = output_directory;
+    output_file_path.push(format!("{}.{}", prefix, ""));
+    let mut current_suffix = String::new();
+    let extra_suffix = opts.extra_suffix.clone().unwrap_or_default();
+    // parts are written to temporary files, so a part with an unclosed array is not left on error
+    let mut output_file = std::io::BufWriter::with_capacity(
+        DEFAULT_BUFFER_SIZE,
+        open_part(
+            &mut current_suffix,
+            &prefix,
+            &mut output_file_path,
+            opts.is_numerical_suffix,
+            &extra_suffix,
+        )?,
+    );
+    let mut availableelements = opts.max_elements;
+    let mut writtenbytes = 0u64;
+    let mut buf = vec![0u8; DEFAULT_BUFFER_SIZE];
+    let mut scanner = JsonArrayScanner::new();
+    loop {
+        let bytesread = input
+            .read(&mut buf)
+            .map_err(|e| Errors::from_io(&e, "reading from input file"))?;
+        if bytesread == 0 {
+            break;
+        }
+        for &b in &buf[0..bytesread] {
+            if !scanner.push(b)? {
+                continue;
+            }
+            let element = &scanner.element;
+            let separator: &[u8] = if writtenbytes == 0 { b"[\n" } else { b",\n" };
+            let is_bytes_exceeded = match opts.max_bytes {
+                // closing bracket is also counted
+                Some(max_bytes) => {
+                    writtenbytes != 0
+                        && writtenbytes + (separator.len() + element.len() + 3) as u64 > max_bytes
+                }
+                None => false,
+            };
+            if availableelements == 0 || is_bytes_exceeded {
+                output_file
+                    .write_all(b"\n]\n")
+                    .map_err(|e| Errors::from_io(&e, "writing output file"))?;
+                commit_buffered(output_file, false)?;
+                output_file = std::io::BufWriter::with_capacity(
+                    DEFAULT_BUFFER_SIZE,
+                    open_part(
+                        &mut current_suffix,
+                        &prefix,
+                        &mut output_file_path,
+                        opts.is_numerical_suffix,
+                        &extra_suffix,
+                    )?,
+                );
+                availableelements = opts.max_elements;
+                writtenbytes = 0;
+            }
+            let separator: &[u8] = if writtenbytes == 0 { b"[\n" } else { b",\n" };
+            if let Some(max_bytes) = opts.max_bytes {
+                if (separator.len() + element.len() + 3) as u64 > max_bytes {
+                    eprintln!(
+                        "warning: an element of {} bytes is larger than max-bytes({})",
+                        element.len(),
+                        max_bytes
+                    );
+                }
+            }
+            output_file
+                .write_all(separator)
+                .and_then(|_| output_file.write_all(element))
+                .map_err(|e| Errors::from_io(&e, "writing output file"))?;
+            writtenbytes += (separator.len() + element.len()) as u64;
+            availableelements -= 1;
+            scanner.clear_element();
+        }
+    }
+    scanner.finish()?;
+    let closing: &[u8] = if writtenbytes == 0 { b"[]\n" } else { b"\n]\n" };
+    output_file
+        .write_all(closing)
+        .map_err(|e| Errors::from_io(&e, "writing output file"))?;
+    commit_buffered(output_file, false)
+}
+
+pub fn create_json_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("json")
+        .about("split top-level json array into smaller arrays")
+        .arg(
+            Arg::with_name("max-elements")
+                .required_unless("max-bytes")
+                .help("max element number per file"),
+        )
+        .arg(
+            Arg::with_name("max-bytes")
+                .long("max-bytes")
+                .takes_value(true)
+                .help("max bytes per file, elements are never cut(used with max-elements, whichever is reached first)"),
+        )
+        .arg(create_input_option())
+        .arg(create_output_option())
+        .arg(create_prefix_option())
+        .arg(create_numeric_suffix_option())
+        .arg(create_extra_suffix_option())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scan(lines: &[&str]) -> Vec<JsonRecord> {
+        let mut scanner = JsonRecordScanner::new();
+        let mut records = Vec::new();
+        for line in lines {
+            scanner.push_line(line, &mut records);
+        }
+        records.extend(scanner.finish());
+        records
+    }
+
+    #[test]
+    fn record_scanner_lines() {
+        let records = scan(&["{\"a\":1}\n", "\n", "[1,\"]\"]\n", "2\n"]);
+        let raws: Vec<&str> = records.iter().map(|v| v.raw.as_str()).collect();
+        assert_eq!(raws, vec!["{\"a\":1}\n", "[1,\"]\"]\n", "2\n"]);
+        assert_eq!(records[1].line_number, 3);
+        assert!(records.iter().all(|v| v.is_complete));
+    }
+
+    #[test]
+    fn record_scanner_pretty_printed() {
+        let records = scan(&[
+            "{\n",
+            "  \"a\": [\n",
+            "    \"{\\\"\"\n",
+            "  ]\n",
+            "}\n",
+            "{}\n",
+        ]);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].raw, "{\n  \"a\": [\n    \"{\\\"\"\n  ]\n}\n");
+        assert!(records[0].is_complete);
+        assert_eq!(records[1].line_number, 6);
+    }
+
+    #[test]
+    fn record_scanner_broken_record() {
+        // an unclosed record ends before the next line starting a value
+        let records = scan(&["{\"a\":1}\n", "{\"b\":\n", "[1]\n", "{\"c\":3}\n"]);
+        let raws: Vec<&str> = records.iter().map(|v| v.raw.as_str()).collect();
+        assert_eq!(
+            raws,
+            vec!["{\"a\":1}\n", "{\"b\":\n", "[1]\n", "{\"c\":3}\n"]
+        );
+        let completes: Vec<bool> = records.iter().map(|v| v.is_complete).collect();
+        assert_eq!(completes, vec![true, false, true, true]);
+        assert_eq!(records[2].line_number, 3);
+    }
+
+    #[test]
+    fn record_scanner_unbalanced() {
+        let records = scan(&["{}}\n", "{\"a\":\n"]);
+        assert_eq!(records.len(), 2);
+        assert!(!records[0].is_complete);
+        // unclosed at the end of input
+        assert!(!records[1].is_complete);
+        assert_eq!(records[1].raw, "{\"a\":\n");
+    }
+
+    #[test]
+    fn record_scanner_unterminated_string() {
+        let records = scan(&["\"abc\n", "1\n"]);
+        assert_eq!(records.len(), 2);
+        assert!(!records[0].is_complete);
+        assert!(records[1].is_complete);
+    }
+
+    /// returns elements of the array, or error message with its offset
+    fn scan_array(input: &str) -> Result<Vec<String>, String> {
+        let mut scanner = JsonArrayScanner::new();
+        let mut elements = Vec::new();
+        for &b in input.as_bytes() {
+            if scanner.push(b).map_err(|e| format!("{:?}", e))? {
+                elements.push(String::from_utf8(scanner.element.clone()).unwrap());
+                scanner.clear_element();
+            }
+        }
+        scanner.finish().map_err(|e| format!("{:?}", e))?;
+        Ok(elements)
+    }
+
+    #[test]
+    fn array_scanner_elements() {
+        assert_eq!(
+            scan_array(" [1, \"a,]\\\"\" ,{\"b\": [2, 3]}, [] ]\n").unwrap(),
+            vec!["1", "\"a,]\\\"\"", "{\"b\": [2, 3]}", "[]"]
+        );
+        assert_eq!(scan_array("[]").unwrap(), Vec::<String>::new());
+        assert_eq!(scan_array("\u{feff}[1]").unwrap(), vec!["1"]);
+    }
+
+    #[test]
+    fn array_scanner_unbalanced_brackets() {
+        assert!(scan_array("[}]")
+            .unwrap_err()
+            .contains("unbalanced bracket at byte 2"));
+        assert!(scan_array("[1]]")
+            .unwrap_err()
+            .contains("unexpected data after array"));
+        assert!(scan_array("[[1]")
+            .unwrap_err()
+            .contains("unexpected end of input"));
+    }
+
+    #[test]
+    fn array_scanner_separators() {
+        assert!(scan_array("[1 2]")
+            .unwrap_err()
+            .contains("unexpected data after array element"));
+        assert!(scan_array("[\"a\"1]")
+            .unwrap_err()
+            .contains("unexpected data after array element"));
+        assert!(scan_array("[{}[]]")
+            .unwrap_err()
+            .contains("unexpected data after array element"));
+        assert!(scan_array("[1,]").unwrap_err().contains("trailing comma"));
+        assert!(scan_array("[1,,2]")
+            .unwrap_err()
+            .contains("empty array element"));
+        assert!(scan_array("{}").unwrap_err().contains("not an array"));
+    }
+
+    #[test]
+    fn split_json_array_broken_input() {
+        let dir = crate::TestDir::new("json-array-broken");
+        let input = dir.join("in.json");
+        let output = dir.join("out");
+        std::fs::create_dir(&output).unwrap();
+        std::fs::write(&input, r#"[1, 2, 3, 4, 5, {"a":"#).unwrap();
+        let opts = JsonArrayOptions::new(2)
+            .with_input(input.to_str())
+            .with_output(output.to_str());
+        assert!(split_json_array(&opts).is_err());
+        // only completed parts are left, without the part of the broken element
+        let mut names: Vec<String> = std::fs::read_dir(&output)
+            .unwrap()
+            .map(|v| v.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["xaa", "xab"]);
+        assert_eq!(
+            std::fs::read_to_string(output.join("xab")).unwrap(),
+            "[\n3,\n4\n]\n"
+        );
+    }
+}
@@ -1,28 +1,66 @@
+extern crate chrono;
 extern crate clap;
 extern crate encoding_rs;
+extern crate regex;
+extern crate serde;
+extern crate serde_json;
+extern crate unicode_linebreak;
+extern crate unicode_segmentation;
+extern crate unicode_width;
+
+mod cdc;
+mod csv;
+mod extract;
+mod framing;
+mod json;
+mod mmap;
+mod parallel;
+mod partfile;
+mod resume;
+mod sparse;
+mod store;
+mod timewindow;
+mod zerocopy;
 
 use clap::{App, Arg, ArgMatches, SubCommand};
-use encoding_rs::Decoder;
-use std::io::{Read, Write};
+use encoding_rs::{CoderResult, Decoder, Encoder};
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, Read, Write};
 use std::iter::FromIterator;
 use std::iter::Iterator;
 use std::str::FromStr;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
 #[derive(Debug, Default)]
 struct BinaryOptions {
     pub max_size: u64,
-    pub delimiter: Option<u8>,
     pub input: Option<String>,
     pub output: Option<String>,
     pub prefix: Option<String>,
     pub extra_suffix: Option<String>,
     pub is_numerical_suffix: bool,
     pub buffer_size: Option<usize>,
+    pub record_size: Option<u64>,
+    pub is_partial_record_error: bool,
+    pub framing: Option<framing::Framing>,
+    pub cdc: Option<cdc::CdcOptions>,
+    pub manifest: Option<String>,
+    pub compare: Option<String>,
+    pub store: Option<String>,
+    pub index: Option<String>,
+    pub only: Option<u64>,
+    pub jobs: usize,
+    pub is_mmap: bool,
+    pub is_sync: bool,
+    pub is_resume: bool,
+    pub is_verbose: bool,
 }
 
 enum StdoutOrFile {
     File(std::fs::File),
-    Stdout(std::io::Stdout),
+    Stdout(std::io::BufWriter<std::io::Stdout>),
 }
 
 impl std::io::Write for StdoutOrFile {
@@ -40,10 +78,13 @@ impl std::io::Write for StdoutOrFile {
     }
 }
 
+/// default size of read buffers and output buffers
+const DEFAULT_BUFFER_SIZE: usize = 1024 * 1024;
+
 #[cfg(windows)]
-const LINE_ENDING: &'static str = "\r\n";
+const LINE_ENDING: &str = "\r\n";
 #[cfg(not(windows))]
-const LINE_ENDING: &'static str = "\n";
+const LINE_ENDING: &str = "\n";
 
 impl BinaryOptions {
     pub fn new(max_size: u64) -> BinaryOptions {
@@ -55,61 +96,244 @@ impl BinaryOptions {
         Default::default()
     }
     pub fn with_input(mut self, s: Option<&str>) -> Self {
-        self.input = s.and_then(|v| Some(String::from(v)));
+        self.input = s.map(String::from);
         self
     }
     pub fn with_output(mut self, s: Option<&str>) -> Self {
-        self.output = s.and_then(|v| Some(String::from(v)));
+        self.output = s.map(String::from);
         self
     }
     pub fn with_prefix(mut self, s: Option<&str>) -> Self {
-        self.prefix = s.and_then(|v| Some(String::from(v)));
+        self.prefix = s.map(String::from);
         self
     }
     pub fn with_extra_suffix(mut self, s: Option<&str>) -> Self {
-        self.extra_suffix = s.and_then(|v| Some(String::from(v)));
+        self.extra_suffix = s.map(String::from);
         self
     }
     pub fn with_is_numerical_suffix(mut self, b: bool) -> Self {
         self.is_numerical_suffix = b;
         self
     }
-    pub fn with_buffer_size(mut self, s: Option<&str>) -> Self {
-        self.buffer_size = match s {
-            Some(v) => match usize::from_str(v) {
-                Ok(x) => Some(x),
-                Err(_) => None,
-            },
-            None => None
-        };
+    pub fn with_buffer_size(mut self, buffer_size: Option<usize>) -> Self {
+        self.buffer_size = buffer_size;
+        self
+    }
+    pub fn with_record_size(mut self, record_size: Option<u64>) -> Self {
+        self.record_size = record_size;
+        self
+    }
+    pub fn with_is_partial_record_error(mut self, b: bool) -> Self {
+        self.is_partial_record_error = b;
+        self
+    }
+    pub fn with_framing(mut self, framing: Option<framing::Framing>) -> Self {
+        self.framing = framing;
+        self
+    }
+    pub fn with_cdc(mut self, cdc: Option<cdc::CdcOptions>) -> Self {
+        self.cdc = cdc;
+        self
+    }
+    pub fn with_manifest(mut self, s: Option<&str>) -> Self {
+        self.manifest = s.map(String::from);
+        self
+    }
+    pub fn with_compare(mut self, s: Option<&str>) -> Self {
+        self.compare = s.map(String::from);
+        self
+    }
+    pub fn with_store(mut self, s: Option<&str>) -> Self {
+        self.store = s.map(String::from);
+        self
+    }
+    pub fn with_index(mut self, s: Option<&str>) -> Self {
+        self.index = s.map(String::from);
+        self
+    }
+    pub fn with_is_verbose(mut self, b: bool) -> Self {
+        self.is_verbose = b;
+        self
+    }
+    pub fn with_jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs;
+        self
+    }
+    pub fn with_only(mut self, only: Option<u64>) -> Self {
+        self.only = only;
+        self
+    }
+    pub fn with_is_mmap(mut self, b: bool) -> Self {
+        self.is_mmap = b;
+        self
+    }
+    pub fn with_is_sync(mut self, b: bool) -> Self {
+        self.is_sync = b;
+        self
+    }
+    pub fn with_is_resume(mut self, b: bool) -> Self {
+        self.is_resume = b;
         self
     }
+    fn parse_u64(s: &str, name: &str) -> Result<u64, Errors> {
+        match s.parse::<u64>() {
+            Ok(v) => Ok(v),
+            Err(e) => Err(Errors::Arg(ArgumentError::new(
+                name,
+                &format!("parse error: {:?}", e),
+            ))),
+        }
+    }
     fn from_arg_matches(matches: &clap::ArgMatches) -> Result<BinaryOptions, Errors> {
-        let max_size = match matches.value_of("max-size") {
-            Some(v) => match v.parse::<u64>() {
-                Ok(v) => v,
-                Err(e) => {
+        let cdc = match matches.value_of("cdc") {
+            Some(_) => Some(cdc::CdcOptions::from_arg_matches(matches)?),
+            None => None,
+        };
+        let framing = match matches.value_of("framing") {
+            Some(v) => Some(framing::Framing::from_str(v)?),
+            None => None,
+        };
+        let record_size = match matches.value_of("record-size") {
+            Some(v) => match Self::parse_u64(v, "record-size")? {
+                0 => {
+                    return Err(Errors::Arg(ArgumentError::new(
+                        "record-size",
+                        "record-size must be larger than 0",
+                    )))
+                }
+                v => Some(v),
+            },
+            None => None,
+        };
+        let max_records = match matches.value_of("max-records") {
+            Some(v) => match Self::parse_u64(v, "max-records")? {
+                0 => {
+                    return Err(Errors::Arg(ArgumentError::new(
+                        "max-records",
+                        "max-records must be larger than 0",
+                    )))
+                }
+                v => Some(v),
+            },
+            None => None,
+        };
+        let max_size = match (matches.value_of("max-size"), max_records) {
+            (_, Some(v)) => v.checked_mul(record_size.unwrap_or(1)).ok_or_else(|| {
+                Errors::Arg(ArgumentError::new(
+                    "max-records",
+                    "max-records is too large",
+                ))
+            })?,
+            (Some(v), None) => match Self::parse_u64(v, "max-size")? {
+                0 => {
                     return Err(Errors::Arg(ArgumentError::new(
                         "max-size",
-                        &format!("parse error: {:?}", e),
+                        "max-size must be larger than 0",
                     )))
                 }
+                v => v,
             },
-            None => {
+            // chunk size is decided by content
+            (None, None) if cdc.is_some() => u64::MAX,
+            (None, None) => {
                 return Err(Errors::Arg(ArgumentError::new(
                     "max-size",
                     "max-size is empty",
                 )))
             }
         };
-        return Ok(Self::new(max_size)
+        // parts are cut only on record boundaries
+        let max_size = match record_size {
+            Some(record_size) if max_size < record_size => {
+                return Err(Errors::Arg(ArgumentError::new(
+                    "max-size",
+                    &format!("max-size is smaller than record-size({})", record_size),
+                )))
+            }
+            Some(record_size) => max_size - max_size % record_size,
+            None => max_size,
+        };
+        Ok(Self::new(max_size)
             .with_input(matches.value_of("input"))
             .with_output(matches.value_of("output"))
             .with_prefix(matches.value_of("prefix"))
             .with_extra_suffix(matches.value_of("extra-suffix"))
             .with_is_numerical_suffix(matches.is_present("numerical-suffix"))
-            .with_buffer_size(matches.value_of("buffer-size"))
-        );
+            .with_buffer_size(parse_buffer_size(matches.value_of("buffer-size"))?)
+            .with_record_size(record_size)
+            .with_is_partial_record_error(matches.value_of("partial-record") == Some("error"))
+            .with_framing(framing)
+            .with_cdc(cdc)
+            .with_manifest(matches.value_of("manifest"))
+            .with_compare(matches.value_of("compare"))
+            .with_store(matches.value_of("store"))
+            .with_index(matches.value_of("index"))
+            .with_only(match matches.value_of("only") {
+                Some(v) => Some(Self::parse_u64(v, "only")?),
+                None => None,
+            })
+            .with_jobs(match matches.value_of("jobs") {
+                Some(v) => match Self::parse_u64(v, "jobs")? {
+                    0 => {
+                        return Err(Errors::Arg(ArgumentError::new(
+                            "jobs",
+                            "jobs must be larger than 0",
+                        )))
+                    }
+                    v => v as usize,
+                },
+                None => 1,
+            })
+            .with_is_mmap(matches.is_present("mmap"))
+            .with_is_sync(matches.is_present("sync"))
+            .with_is_resume(matches.is_present("resume"))
+            .with_is_verbose(matches.is_present("verbose")))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum WrapUnit {
+    #[default]
+    Chars,
+    Graphemes,
+    Width,
+    Bytes,
+}
+
+impl FromStr for WrapUnit {
+    type Err = Errors;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "chars" => Ok(WrapUnit::Chars),
+            "graphemes" => Ok(WrapUnit::Graphemes),
+            "width" => Ok(WrapUnit::Width),
+            "bytes" => Ok(WrapUnit::Bytes),
+            _ => Err(Errors::Arg(ArgumentError::new(
+                "wrap-unit",
+                &format!("invalid wrap unit:{}", s),
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum WrapMode {
+    #[default]
+    Hard,
+    Words,
+}
+
+impl FromStr for WrapMode {
+    type Err = Errors;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hard" => Ok(WrapMode::Hard),
+            "words" => Ok(WrapMode::Words),
+            _ => Err(Errors::Arg(ArgumentError::new(
+                "wrap",
+                &format!("invalid wrap mode:{}", s),
+            ))),
+        }
     }
 }
 
@@ -117,6 +341,19 @@ impl BinaryOptions {
 struct LineOptions {
     pub max_lines: u64,
     pub max_chars: Option<u64>,
+    pub max_bytes: Option<u64>,
+    pub header_lines: u64,
+    pub split_before: Option<Regex>,
+    pub split_after: Option<Regex>,
+    pub record_start: Option<Regex>,
+    pub is_paragraph: bool,
+    pub is_zero_terminated: bool,
+    pub buffer_size: Option<usize>,
+    pub is_mmap: bool,
+    pub time_window: Option<timewindow::TimeWindow>,
+    pub wrap_unit: WrapUnit,
+    pub wrap_mode: WrapMode,
+    pub continuation_prefix: Option<String>,
     pub input: Option<String>,
     pub output: Option<String>,
     pub prefix: Option<String>,
@@ -138,24 +375,88 @@ impl LineOptions {
         self.max_chars = max_chars;
         self
     }
+    pub fn with_max_bytes(mut self, max_bytes: Option<u64>) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+    pub fn with_header_lines(mut self, header_lines: u64) -> Self {
+        self.header_lines = header_lines;
+        self
+    }
+    pub fn with_split_before(mut self, re: Option<Regex>) -> Self {
+        self.split_before = re;
+        self
+    }
+    pub fn with_split_after(mut self, re: Option<Regex>) -> Self {
+        self.split_after = re;
+        self
+    }
+    pub fn with_record_start(mut self, re: Option<Regex>) -> Self {
+        self.record_start = re;
+        self
+    }
+    pub fn with_is_paragraph(mut self, b: bool) -> Self {
+        self.is_paragraph = b;
+        self
+    }
+    pub fn with_is_zero_terminated(mut self, b: bool) -> Self {
+        self.is_zero_terminated = b;
+        self
+    }
+    pub fn with_buffer_size(mut self, buffer_size: Option<usize>) -> Self {
+        self.buffer_size = buffer_size;
+        self
+    }
+    pub fn with_is_mmap(mut self, b: bool) -> Self {
+        self.is_mmap = b;
+        self
+    }
+    /// terminator of lines added by wrapping
+    pub fn line_ending(&self) -> &'static str {
+        if self.is_zero_terminated {
+            "\0"
+        } else {
+            LINE_ENDING
+        }
+    }
+    /// whether lines are grouped into records which are never split
+    pub fn is_record_mode(&self) -> bool {
+        self.record_start.is_some() || self.is_paragraph
+    }
+    pub fn with_time_window(mut self, time_window: Option<timewindow::TimeWindow>) -> Self {
+        self.time_window = time_window;
+        self
+    }
+    pub fn with_wrap_unit(mut self, wrap_unit: WrapUnit) -> Self {
+        self.wrap_unit = wrap_unit;
+        self
+    }
+    pub fn with_wrap_mode(mut self, wrap_mode: WrapMode) -> Self {
+        self.wrap_mode = wrap_mode;
+        self
+    }
+    pub fn with_continuation_prefix(mut self, s: Option<&str>) -> Self {
+        self.continuation_prefix = s.map(String::from);
+        self
+    }
     pub fn with_prefix(mut self, prefix: Option<&str>) -> Self {
-        self.prefix = prefix.and_then(|v| Some(String::from(v)));
+        self.prefix = prefix.map(String::from);
         self
     }
     pub fn with_input(mut self, s: Option<&str>) -> Self {
-        self.input = s.and_then(|v| Some(String::from(v)));
+        self.input = s.map(String::from);
         self
     }
     pub fn with_output(mut self, s: Option<&str>) -> Self {
-        self.output = s.and_then(|v| Some(String::from(v)));
+        self.output = s.map(String::from);
         self
     }
     pub fn with_encoding(mut self, s: Option<&str>) -> Self {
-        self.encoding = s.and_then(|v| Some(String::from(v)));
+        self.encoding = s.map(String::from);
         self
     }
     pub fn with_extra_suffix(mut self, s: Option<&str>) -> Self {
-        self.extra_suffix = s.and_then(|v| Some(String::from(v)));
+        self.extra_suffix = s.map(String::from);
         self
     }
     pub fn with_is_numerical_suffix(mut self, b: bool) -> Self {
@@ -165,17 +466,38 @@ impl LineOptions {
     fn parse_u64(s: &str, name: &str) -> Result<u64, Errors> {
         match s.parse::<u64>() {
             Ok(v) => Ok(v),
-            Err(e) => {
-                return Err(Errors::Arg(ArgumentError::new(
+            Err(e) => Err(Errors::Arg(ArgumentError::new(
+                name,
+                &format!("parse error: {:?}", e),
+            ))),
+        }
+    }
+    fn parse_regex(s: Option<&str>, name: &str) -> Result<Option<Regex>, Errors> {
+        match s {
+            Some(v) => match Regex::new(v) {
+                Ok(re) => Ok(Some(re)),
+                Err(e) => Err(Errors::Arg(ArgumentError::new(
                     name,
-                    &format!("parse error: {:?}", e),
-                )))
-            }
+                    &format!("invalid regular expression: {}", e),
+                ))),
+            },
+            None => Ok(None),
         }
     }
     pub fn from_arg_matches(matches: &clap::ArgMatches) -> Result<LineOptions, Errors> {
+        let max_bytes = match matches.value_of("max-bytes") {
+            Some(v) => Some(Self::parse_u64(v, "max-bytes")?),
+            None => None,
+        };
         let max_size = match matches.value_of("max-lines") {
             Some(v) => Self::parse_u64(v, "max-size")?,
+            None if max_bytes.is_some()
+                || matches.is_present("split-before")
+                || matches.is_present("split-after")
+                || matches.is_present("time-window") =>
+            {
+                u64::MAX
+            }
             None => {
                 return Err(Errors::Arg(ArgumentError::new(
                     "max-size",
@@ -187,9 +509,72 @@ impl LineOptions {
             Some(v) => Some(Self::parse_u64(v, "max-chars")?),
             None => None,
         };
+        let header_lines = match matches.value_of("header-lines") {
+            Some(v) => Self::parse_u64(v, "header-lines")?,
+            None => 0,
+        };
+        let wrap_unit = match matches.value_of("wrap-unit") {
+            Some(v) => WrapUnit::from_str(v)?,
+            None => WrapUnit::default(),
+        };
+        let wrap_mode = match matches.value_of("wrap") {
+            Some(v) => WrapMode::from_str(v)?,
+            None => WrapMode::default(),
+        };
+        let time_window = match matches.value_of("time-window") {
+            Some(v) => {
+                let time_format = matches.value_of("time-format");
+                let name_format = matches.value_of("time-name-format");
+                for (format, name) in [
+                    (time_format, "time-format"),
+                    (name_format, "time-name-format"),
+                ] {
+                    if let Some(format) = format {
+                        timewindow::TimeWindow::check_format(format, name)?;
+                    }
+                }
+                let out_of_order = match matches.value_of("out-of-order") {
+                    Some(v) => timewindow::OutOfOrderPolicy::from_str(v)?,
+                    None => timewindow::OutOfOrderPolicy::default(),
+                };
+                Some(
+                    timewindow::TimeWindow::new(timewindow::TimeWindow::parse_window(v)?)
+                        .with_time_format(time_format)
+                        .with_time_pattern(Self::parse_regex(
+                            matches.value_of("time-pattern"),
+                            "time-pattern",
+                        )?)
+                        .with_name_format(name_format)
+                        .with_out_of_order(out_of_order),
+                )
+            }
+            None => None,
+        };
         Ok(Self::new(max_size)
             .with_prefix(matches.value_of("prefix"))
             .with_max_chars(max_chars)
+            .with_max_bytes(max_bytes)
+            .with_header_lines(header_lines)
+            .with_split_before(Self::parse_regex(
+                matches.value_of("split-before"),
+                "split-before",
+            )?)
+            .with_split_after(Self::parse_regex(
+                matches.value_of("split-after"),
+                "split-after",
+            )?)
+            .with_record_start(Self::parse_regex(
+                matches.value_of("record-start"),
+                "record-start",
+            )?)
+            .with_is_paragraph(matches.is_present("paragraph"))
+            .with_is_zero_terminated(matches.is_present("zero-terminated"))
+            .with_buffer_size(parse_buffer_size(matches.value_of("buffer-size"))?)
+            .with_is_mmap(matches.is_present("mmap"))
+            .with_time_window(time_window)
+            .with_wrap_unit(wrap_unit)
+            .with_wrap_mode(wrap_mode)
+            .with_continuation_prefix(matches.value_of("continuation-prefix"))
             .with_input(matches.value_of("input"))
             .with_output(matches.value_of("output"))
             .with_encoding(matches.value_of("encoding"))
@@ -230,6 +615,17 @@ enum Errors {
     Arg(ArgumentError),
 }
 
+impl std::fmt::Display for Errors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Errors::Io(e) => write!(f, "{}", e),
+            Errors::Glob(e) => write!(f, "{}", e),
+            Errors::Pattern(e) => write!(f, "{}", e),
+            Errors::Arg(e) => write!(f, "{}", e),
+        }
+    }
+}
+
 impl Errors {
     pub fn from_io(e: &std::io::Error, prefix: &str) -> Errors {
         Errors::Io(std::io::Error::new(
@@ -239,6 +635,30 @@ impl Errors {
     }
 }
 
+fn flush_part(output_file: &mut std::io::BufWriter<std::fs::File>) -> Result<(), Errors> {
+    output_file
+        .flush()
+        .map_err(|e| Errors::from_io(&e, "writing to output file"))
+}
+
+/// buffer size must be a positive number, default is used if not given
+fn parse_buffer_size(s: Option<&str>) -> Result<Option<usize>, Errors> {
+    match s {
+        Some(v) => match v.parse::<usize>() {
+            Ok(0) => Err(Errors::Arg(ArgumentError::new(
+                "buffer-size",
+                "buffer-size must be larger than 0",
+            ))),
+            Ok(v) => Ok(Some(v)),
+            Err(e) => Err(Errors::Arg(ArgumentError::new(
+                "buffer-size",
+                &format!("parse error: {:?}", e),
+            ))),
+        },
+        None => Ok(None),
+    }
+}
+
 fn get_file_or_stdin(filepath: &Option<String>) -> Result<Box<dyn Read>, Errors> {
     if let Some(filepath) = filepath {
         match std::fs::File::open(filepath) {
@@ -264,17 +684,22 @@ fn ensure_dir(dir: &std::path::Path) -> Result<(), Errors> {
             }
             Ok(())
         }
-        Err(_) => std::fs::create_dir_all(dir.to_owned())
-            .or_else(|e| Err(Errors::from_io(&e, "creating output directory"))),
+        Err(_) => std::fs::create_dir_all(dir)
+            .map_err(|e| Errors::from_io(&e, "creating output directory")),
     }?;
     Ok(())
 }
 
+type DecodedLines = (usize, Vec<(String, bool)>, bool);
+
+/// CR of line endings is removed unless is_cr_kept, so CRLF is read as LF
 fn get_lines_from_buf(
     decoder: &mut Decoder,
     bytes: &[u8],
     is_cr: bool,
-) -> Result<(usize, Vec<(String, bool)>, bool), Errors> {
+    is_zero_terminated: bool,
+    is_cr_kept: bool,
+) -> Result<DecodedLines, Errors> {
     let mut decoded = String::new();
     let mut strbuf = String::new();
     let mut lines: Vec<(String, bool)> = Vec::new();
@@ -283,7 +708,14 @@ fn get_lines_from_buf(
     let mut is_cr_found = is_cr;
     let (_, readchars, _) = decoder.decode_to_string(bytes, &mut decoded, false);
     for c in decoded.chars() {
-        if is_cr_found {
+        if is_zero_terminated {
+            // CR and LF are part of the line
+            strbuf.push(c);
+            if c == '\0' {
+                lines.push((strbuf.clone(), true));
+                strbuf.clear();
+            }
+        } else if is_cr_found {
             if c == '\r' {
                 // found CR CR
                 lines.push((strbuf.clone(), true));
@@ -310,23 +742,27 @@ fn get_lines_from_buf(
         } else if c != '\r' {
             strbuf.push(c);
         } else {
+            if is_cr_kept {
+                strbuf.push(c);
+            }
             is_cr_found = true;
         }
     }
-    if strbuf.len() != 0 {
+    if !strbuf.is_empty() {
         lines.push((strbuf.clone(), false));
     }
-    return Ok((readchars, lines, is_cr_found));
+    Ok((readchars, lines, is_cr_found))
 }
 
-fn open_file(
+/// sets the file name of the part to output_file_path and advances suffix
+fn next_part_path(
     suffixstr: &mut String,
     prefix: &str,
     output_file_path: &mut std::path::PathBuf,
     is_numerical_suffix: bool,
     extra_suffix: &str,
-) -> Result<std::fs::File, Errors> {
-    if suffixstr == "" {
+) {
+    if suffixstr.is_empty() {
         suffixstr.push_str(match is_numerical_suffix {
             true => "0",
             false => "aa",
@@ -334,20 +770,97 @@ fn open_file(
     }
     let next_suffixstr = get_next_suffix(suffixstr, is_numerical_suffix);
     output_file_path.set_file_name(format!("{}{}{}", prefix, suffixstr, extra_suffix));
+    suffixstr.clear();
+    suffixstr.push_str(next_suffixstr.as_str());
+}
+
+fn open_file(
+    suffixstr: &mut String,
+    prefix: &str,
+    output_file_path: &mut std::path::PathBuf,
+    is_numerical_suffix: bool,
+    extra_suffix: &str,
+) -> Result<std::fs::File, Errors> {
+    next_part_path(
+        suffixstr,
+        prefix,
+        output_file_path,
+        is_numerical_suffix,
+        extra_suffix,
+    );
     let output_file = std::fs::OpenOptions::new()
         .create(true)
         .write(true)
         .truncate(true)
         .open(&output_file_path)
-        .or_else(|e| Err(Errors::from_io(&e, "in opening file")))?;
+        .map_err(|e| Errors::from_io(&e, "in opening file"))?;
     output_file
         .set_len(0)
-        .or_else(|e| Err(Errors::from_io(&e, "truncating file")))?;
-    suffixstr.clear();
-    suffixstr.push_str(next_suffixstr.as_str());
+        .map_err(|e| Errors::from_io(&e, "truncating file"))?;
     Ok(output_file)
 }
 
+/// replaces characters which cannot be used in file name
+fn sanitize_file_name(s: &str) -> String {
+    let ret: String = s
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let trimmed = ret.trim_end_matches(['.', ' ']);
+    if trimmed.is_empty() {
+        "_".repeat(std::cmp::max(ret.len(), 1))
+    } else if trimmed.len() != ret.len() {
+        format!("{}{}", trimmed, "_".repeat(ret.len() - trimmed.len()))
+    } else {
+        ret
+    }
+}
+
+/// expands "$1", "${1}" or "${name}" in template with captured texts sanitized for file name,
+/// references are removed when caps is None. "$$" is expanded to "$".
+fn expand_name_template(template: &str, caps: Option<&regex::Captures>) -> String {
+    let mut ret = String::new();
+    let mut rest = template;
+    while let Some(pos) = rest.find('$') {
+        ret.push_str(&rest[..pos]);
+        rest = &rest[pos + 1..];
+        let (name, len) = if let Some(stripped) = rest.strip_prefix('{') {
+            match stripped.find('}') {
+                Some(end) => (&stripped[..end], end + 2),
+                None => ("", 0),
+            }
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            (&rest[..end], end)
+        };
+        if name.is_empty() {
+            if rest.starts_with('$') {
+                rest = &rest[1..];
+            }
+            ret.push('$');
+            continue;
+        }
+        if let Some(caps) = caps {
+            let m = match name.parse::<usize>() {
+                Ok(i) => caps.get(i),
+                Err(_) => caps.name(name),
+            };
+            if let Some(m) = m {
+                ret.push_str(&sanitize_file_name(m.as_str()));
+            }
+        }
+        rest = &rest[len..];
+    }
+    ret.push_str(rest);
+    ret
+}
+
 fn get_next_suffix(current_suffix: &str, is_numerical_suffix: bool) -> String {
     let mut ret = String::new();
     if !is_numerical_suffix {
@@ -359,14 +872,14 @@ fn get_next_suffix(current_suffix: &str, is_numerical_suffix: bool) -> String {
                 if should_increment {
                     if item != 'z' {
                         st.push((item as u8 + 1) as char);
-                        return (st, false);
+                        (st, false)
                     } else {
                         st.push('a');
-                        return (st, true);
+                        (st, true)
                     }
                 } else {
                     st.push(item);
-                    return (st, false);
+                    (st, false)
                 }
             },
         );
@@ -385,6 +898,7 @@ fn get_next_suffix(current_suffix: &str, is_numerical_suffix: bool) -> String {
     ret
 }
 
+#[allow(clippy::too_many_arguments)]
 fn rolling_file(
     current_suffix: &mut String,
     prefix: &str,
@@ -393,231 +907,808 @@ fn rolling_file(
     max_lines: u64,
     is_numerical: bool,
     extra_suffix: &str,
+    header: &[u8],
 ) -> Result<std::fs::File, Errors> {
     // output_file = std::fs::File::create(output_file_path.to_owned()).or_else(|e| Err(Errors::Io(e)))?;
-    let output_file = open_file(
+    let mut output_file = open_file(
         current_suffix,
-        &prefix,
+        prefix,
         output_file_path,
         is_numerical,
         extra_suffix,
     )?;
+    output_file
+        .write_all(header)
+        .map_err(|e| Errors::from_io(&e, "writing header"))?;
     *availablelines = max_lines;
     Ok(output_file)
 }
 
-fn is_line_ending(s: &str) -> bool {
-    s == "\r" || s == "\n" || s == "\r\n"
+fn split_line_ending(line: &str) -> (&str, &str) {
+    let content = line.trim_end_matches(['\r', '\n', '\0']);
+    (content, &line[content.len()..])
 }
 
-fn split_text_encoding(opts: &LineOptions) -> Result<(), Errors> {
-    let mut input = get_file_or_stdin(&opts.input)?;
-    let mut availablelines = opts.max_lines;
-    let output_directory = match &opts.output {
-        Some(v) => std::path::PathBuf::from_str(v.as_str()).unwrap(),
-        None => std::env::current_dir()
-            .or_else(|e| Err(Errors::from_io(&e, "getting output_directory")))?,
+fn measure_str(s: &str, wrap_unit: WrapUnit) -> u64 {
+    match wrap_unit {
+        WrapUnit::Chars => s.chars().count() as u64,
+        WrapUnit::Graphemes => s.graphemes(true).count() as u64,
+        WrapUnit::Width => UnicodeWidthStr::width(s) as u64,
+        WrapUnit::Bytes => s.len() as u64,
+    }
+}
+
+/// split line into pieces which do not exceed max_chars measured in wrap_unit.
+/// line ending is kept on the last piece and is not measured.
+/// in words mode, pieces are cut at the last line break opportunity(UAX #14) and
+/// trailing whitespaces are kept on the previous piece, so joining pieces restores the line.
+fn wrap_line(line: &str, max_chars: u64, opts: &LineOptions) -> Vec<String> {
+    let (content, line_ending) = split_line_ending(line);
+    let wrap_unit = opts.wrap_unit;
+    let units: Vec<(usize, &str)> = match wrap_unit {
+        WrapUnit::Chars => content
+            .char_indices()
+            .map(|(i, c)| (i, &content[i..i + c.len_utf8()]))
+            .collect(),
+        _ => content.grapheme_indices(true).collect(),
     };
-    let (mut decoder, mut encoder) = match &opts.encoding {
-        Some(v) => match encoding_rs::Encoding::for_label(v.as_bytes()) {
-            Some(enc) => (enc.new_decoder(), enc.new_encoder()),
-            None => {
-                return Err(Errors::Arg(ArgumentError::new(
-                    "encoding",
-                    &format!("invalid encoding name:{}", v),
-                )))
+    let breaks: Vec<usize> = match opts.wrap_mode {
+        WrapMode::Hard => Vec::new(),
+        WrapMode::Words => unicode_linebreak::linebreaks(content)
+            .map(|(i, _)| i)
+            .filter(|i| *i < content.len())
+            .collect(),
+    };
+    let continuation_prefix = opts.continuation_prefix.as_deref().unwrap_or("");
+    let mut pieces: Vec<String> = Vec::new();
+    let mut start = 0usize;
+    let mut measured = 0u64;
+    for (offset, unit) in units {
+        let size = measure_str(unit, wrap_unit);
+        let is_hanging_space =
+            opts.wrap_mode == WrapMode::Words && unit.chars().all(char::is_whitespace);
+        if offset > start && measured + size > max_chars && !is_hanging_space {
+            let cut = breaks
+                .iter()
+                .rev()
+                .find(|b| **b > start && **b <= offset)
+                .copied()
+                .unwrap_or(offset);
+            let mut piece = String::new();
+            if !pieces.is_empty() {
+                piece.push_str(continuation_prefix);
             }
+            piece.push_str(&content[start..cut]);
+            pieces.push(piece);
+            start = cut;
+            measured = measure_str(continuation_prefix, wrap_unit)
+                + measure_str(&content[start..offset], wrap_unit);
+        }
+        measured += size;
+    }
+    let mut piece = String::new();
+    if !pieces.is_empty() {
+        piece.push_str(continuation_prefix);
+    }
+    piece.push_str(&content[start..]);
+    piece.push_str(line_ending);
+    pieces.push(piece);
+    pieces
+}
+
+fn get_text_codec(encoding: &Option<String>) -> Result<(Decoder, Encoder), Errors> {
+    match encoding {
+        Some(v) => match encoding_rs::Encoding::for_label(v.as_bytes()) {
+            Some(enc) => Ok((enc.new_decoder(), enc.new_encoder())),
+            None => Err(Errors::Arg(ArgumentError::new(
+                "encoding",
+                &format!("invalid encoding name:{}", v),
+            ))),
         },
-        None => (
+        None => Ok((
             encoding_rs::UTF_8.new_decoder(),
             encoding_rs::UTF_8.new_encoder(),
-        ),
-    };
-    ensure_dir(&output_directory)?;
-    let mut output_file_path = std::path::PathBuf::from(output_directory);
-    let prefix = opts.prefix.clone().unwrap_or(String::from("x"));
-    output_file_path.push(format!("{}.{}", prefix, ""));
-    let (max_chars, is_max_chars_set) = match opts.max_chars {
-        Some(v) => (v, true),
-        None => (0, false),
-    };
-    let mut current_suffix = String::new();
-    let extra_suffix = opts.extra_suffix.clone().unwrap_or(String::new());
-    let mut output_file = open_file(
-        &mut current_suffix,
-        &prefix,
-        &mut output_file_path,
-        opts.is_numerical_suffix,
-        &extra_suffix,
-    )?;
-    let mut buf = [0u8; 1024];
-    let mut readoffset = 0;
-    let mut wbuf: Vec<u8> = Vec::new();
-    wbuf.reserve(4096);
-    let mut is_cr = false;
+        )),
+    }
+}
+
+/// encodes whole s into dst, dst is grown as needed
+fn encode_to_vec(encoder: &mut Encoder, s: &str, dst: &mut Vec<u8>) {
+    let mut src = s;
     loop {
-        let bytesread = input
-            .read(&mut buf[readoffset..])
-            .or_else(|e| Err(Errors::from_io(&e, "reading file")))?;
-        if bytesread == 0 {
+        let needed = encoder
+            .max_buffer_length_from_utf8_if_no_unmappables(src.len())
+            .unwrap_or(src.len());
+        dst.reserve(needed);
+        let (result, read, _) = encoder.encode_from_utf8_to_vec(src, dst, false);
+        src = &src[read..];
+        if result == CoderResult::InputEmpty {
             break;
         }
-        let (readfrombuf, lines, is_cr_found) =
-            get_lines_from_buf(&mut decoder, &buf[0..bytesread + readoffset], is_cr)?;
-        is_cr = is_cr_found;
-        if readfrombuf < bytesread + readoffset {
-            let mut tmp: Vec<u8> = Vec::new();
-            tmp.resize(bytesread + readoffset - readfrombuf, 0);
-            tmp.clone_from_slice(&buf[readfrombuf..bytesread + readoffset]);
-            buf.clone_from_slice(&tmp);
-            readoffset = bytesread + readoffset - readfrombuf;
-        }
-        for (line, is_last_newline) in lines {
-            if is_max_chars_set {
-                let mut strbuf = String::new();
-                let mut charcount = 0 as usize;
-                for c in line.chars() {
-                    strbuf.push(c);
-                    charcount += 1;
-                    if charcount >= max_chars as usize {
-                        if availablelines == 0 {
-                            let next_output_file = rolling_file(
-                                &mut current_suffix,
-                                &prefix,
-                                &mut output_file_path,
-                                &mut availablelines,
-                                opts.max_lines,
-                                opts.is_numerical_suffix,
-                                &extra_suffix,
-                            )?;
-                            output_file = next_output_file;
-                        }
-                        wbuf.reserve(strbuf.len());
-                        let (_, _, _) = encoder.encode_from_utf8_to_vec(&strbuf, &mut wbuf, false);
-                        output_file
-                            .write(&wbuf)
-                            .or_else(|e| Err(Errors::from_io(&e, "writing to output file")))?;
-                        if !strbuf.ends_with("\r") && !strbuf.ends_with("\n") {
-                            output_file
-                                .write(LINE_ENDING.as_bytes())
-                                .or_else(|e| Err(Errors::from_io(&e, "writing newline")))?;
-                        }
-                        availablelines -= 1;
-                        wbuf.clear();
-                        strbuf.clear();
-                        charcount = 0;
-                    }
-                }
-                if strbuf.len() != 0 && !is_line_ending(&strbuf) {
-                    if availablelines == 0 {
-                        let next_output_file = rolling_file(
-                            &mut current_suffix,
-                            &prefix,
-                            &mut output_file_path,
-                            &mut availablelines,
-                            opts.max_lines,
-                            opts.is_numerical_suffix,
-                            &extra_suffix,
-                        )?;
-                        output_file = next_output_file;
-                    }
-                    wbuf.reserve(strbuf.len());
-                    let (_, _, _) = encoder.encode_from_utf8_to_vec(&strbuf, &mut wbuf, false);
-                    output_file
-                        .write(&wbuf)
-                        .or_else(|e| Err(Errors::from_io(&e, "writing to output file")))?;
-                    if is_last_newline {
-                        availablelines -= 1;
-                    }
-                    wbuf.clear();
-                    strbuf.clear();
-                }
-            } else {
-                if availablelines == 0 {
-                    let next_output_file = rolling_file(
-                        &mut current_suffix,
-                        &prefix,
-                        &mut output_file_path,
-                        &mut availablelines,
-                        opts.max_lines,
-                        opts.is_numerical_suffix,
-                        &extra_suffix,
-                    )?;
-                    output_file = next_output_file;
-                }
-                wbuf.reserve(line.len());
-                let (_, _, _) = encoder.encode_from_utf8_to_vec(&line, &mut wbuf, false);
-                output_file
-                    .write(&wbuf)
-                    .or_else(|e| Err(Errors::from_io(&e, "writing to output file")))?;
-                if is_last_newline {
-                    availablelines -= 1;
-                }
-                wbuf.clear();
-            }
-        }
     }
-    Ok(())
 }
 
-fn split_binary(opts: &BinaryOptions) -> Result<(), Errors> {
-    let mut input = get_file_or_stdin(&opts.input)?;
-    let buffer_size = opts.buffer_size.unwrap_or(1024usize);
-    let mut buf = Vec::<u8>::with_capacity(buffer_size);
-    buf.resize(buffer_size, 0);
-    let output_directory = match &opts.output {
+fn get_output_directory(output: &Option<String>) -> Result<std::path::PathBuf, Errors> {
+    let output_directory = match output {
         Some(v) => std::path::PathBuf::from(v),
-        None => match std::env::current_dir()
-            .or_else(|e| Err(Errors::from_io(&e, "getting current directory")))
-        {
-            Ok(v) => v,
-            Err(e) => return Err(e),
-        },
+        None => {
+            std::env::current_dir().map_err(|e| Errors::from_io(&e, "getting output_directory"))?
+        }
     };
     ensure_dir(&output_directory)?;
-    let prefix = opts.prefix.clone().unwrap_or(String::from("x"));
-    let mut output_file_path = output_directory.to_path_buf();
-    output_file_path.push(format!("{}.{}", prefix, ""));
-    let mut current_suffix = String::new();
-    let extra_suffix = opts.extra_suffix.clone().unwrap_or_default();
-    let mut output_file = open_file(
-        &mut current_suffix,
-        &prefix,
-        &mut output_file_path,
-        opts.is_numerical_suffix,
-        &extra_suffix,
-    )?;
-    let mut available = opts.max_size;
-    loop {
-        let bytesread = input
-            .read(&mut buf)
-            .or_else(|e| Err(Errors::from_io(&e, "reading from input file")))?;
-        if bytesread == 0 {
-            break;
+    Ok(output_directory)
+}
+
+/// reads decoded text from input and returns only complete lines,
+/// an incomplete last line is returned at the end of input.
+struct LineReader {
+    input: Box<dyn Read>,
+    mapping: Option<(memmap2::Mmap, usize)>,
+    decoder: Decoder,
+    buf: Vec<u8>,
+    is_cr: bool,
+    pending: String,
+    is_eof: bool,
+    is_zero_terminated: bool,
+    is_cr_kept: bool,
+}
+
+impl LineReader {
+    pub fn new(input: Box<dyn Read>, decoder: Decoder) -> Self {
+        LineReader {
+            input,
+            mapping: None,
+            decoder,
+            buf: vec![0u8; DEFAULT_BUFFER_SIZE],
+            is_cr: false,
+            pending: String::new(),
+            is_eof: false,
+            is_zero_terminated: false,
+            is_cr_kept: false,
         }
-        let mut remaining = bytesread;
-        let mut offset = 0usize;
-        while remaining > 0 {
-            let bytesavailable = std::cmp::min(remaining as usize, available as usize);
-            output_file
-                .write(&buf[offset..offset + bytesavailable])
-                .or_else(|e| Err(Errors::from_io(&e, "writing output file")))?;
-            remaining -= bytesavailable;
-            available -= bytesavailable as u64;
-            offset += bytesavailable;
-            if available == 0 && remaining != 0 {
-                let next_output_file = rolling_file(
+    }
+    pub fn with_buffer_size(mut self, buffer_size: Option<usize>) -> Self {
+        if let Some(buffer_size) = buffer_size {
+            self.buf = vec![0u8; buffer_size];
+        }
+        self
+    }
+    /// lines are decoded directly from the mapping instead of input,
+    /// buffer size is the size of a slice decoded at once
+    pub fn with_mapping(mut self, mapping: Option<memmap2::Mmap>) -> Self {
+        self.mapping = mapping.map(|v| (v, 0));
+        self
+    }
+    /// lines are terminated by NUL instead of line endings
+    pub fn with_zero_terminated(mut self, b: bool) -> Self {
+        self.is_zero_terminated = b;
+        self
+    }
+    /// CRLF and lone CR line endings are kept as they are instead of being read as LF
+    pub fn with_cr_kept(mut self, b: bool) -> Self {
+        self.is_cr_kept = b;
+        self
+    }
+    /// returns None when all lines are read
+    pub fn read_lines(&mut self) -> Result<Option<Vec<(String, bool)>>, Errors> {
+        if self.is_eof {
+            return Ok(None);
+        }
+        let bytes = match &mut self.mapping {
+            Some((mapping, offset)) => {
+                let start = *offset;
+                *offset = std::cmp::min(start + self.buf.len(), mapping.len());
+                &mapping[start..*offset]
+            }
+            None => {
+                let bytesread = self
+                    .input
+                    .read(&mut self.buf)
+                    .map_err(|e| Errors::from_io(&e, "reading file"))?;
+                &self.buf[0..bytesread]
+            }
+        };
+        let bytesread = bytes.len();
+        let (_, mut lines, is_cr_found) = get_lines_from_buf(
+            &mut self.decoder,
+            bytes,
+            self.is_cr,
+            self.is_zero_terminated,
+            self.is_cr_kept,
+        )?;
+        self.is_cr = is_cr_found;
+        if !self.pending.is_empty() {
+            match lines.first_mut() {
+                Some((first, _)) => first.insert_str(0, &self.pending),
+                None => lines.push((self.pending.clone(), false)),
+            }
+            self.pending.clear();
+        }
+        if bytesread == 0 {
+            self.is_eof = true;
+        } else if let Some((_, false)) = lines.last() {
+            // keep incomplete line until its line ending arrives
+            self.pending = lines.pop().map(|(v, _)| v).unwrap_or_default();
+        }
+        Ok(Some(lines))
+    }
+}
+
+/// writes encoded records to output files, rolling to the next file
+/// when max_records or max_bytes is reached.
+struct TextPartWriter {
+    output_file: Option<std::io::BufWriter<partfile::PartFile>>,
+    output_file_path: std::path::PathBuf,
+    prefix: String,
+    current_suffix: String,
+    extra_suffix: String,
+    is_numerical_suffix: bool,
+    encoder: Encoder,
+    max_records: u64,
+    max_bytes: Option<u64>,
+    availablerecords: u64,
+    writtenbytes: u64,
+    is_roll_requested: bool,
+    is_temporary: bool,
+    header: Vec<u8>,
+    wbuf: Vec<u8>,
+}
+
+impl TextPartWriter {
+    pub fn new(
+        output_directory: std::path::PathBuf,
+        prefix: Option<&str>,
+        extra_suffix: Option<&str>,
+        is_numerical_suffix: bool,
+        encoder: Encoder,
+        max_records: u64,
+        max_bytes: Option<u64>,
+    ) -> Self {
+        let mut output_file_path = output_directory;
+        let prefix = prefix.unwrap_or("x").to_owned();
+        output_file_path.push(format!("{}.{}", prefix, ""));
+        TextPartWriter {
+            output_file: None,
+            output_file_path,
+            prefix,
+            current_suffix: String::new(),
+            extra_suffix: extra_suffix.unwrap_or_default().to_owned(),
+            is_numerical_suffix,
+            encoder,
+            max_records,
+            max_bytes,
+            availablerecords: max_records,
+            writtenbytes: 0,
+            is_roll_requested: false,
+            is_temporary: false,
+            header: Vec::new(),
+            wbuf: Vec::with_capacity(4096),
+        }
+    }
+    /// parts are written to temporary files and renamed when they are completed
+    pub fn with_temporary_parts(mut self, b: bool) -> Self {
+        self.is_temporary = b;
+        self
+    }
+    /// header is written as is and repeated in every part
+    pub fn write_header(&mut self, s: &str) -> Result<(), Errors> {
+        encode_to_vec(&mut self.encoder, s, &mut self.wbuf);
+        if let Some(output_file) = &mut self.output_file {
+            output_file
+                .write_all(&self.wbuf)
+                .map_err(|e| Errors::from_io(&e, "writing header"))?;
+            self.writtenbytes += self.wbuf.len() as u64;
+        }
+        self.header.extend_from_slice(&self.wbuf);
+        self.wbuf.clear();
+        Ok(())
+    }
+    /// next record is written to a new part, named with prefix if it is given
+    pub fn start_next_part(&mut self, prefix: Option<String>) {
+        if let Some(prefix) = prefix {
+            self.prefix = prefix;
+        }
+        self.is_roll_requested = true;
+    }
+    /// write a record, is_counted is false when the record is not complete(last line without line ending)
+    pub fn write_record(&mut self, s: &str, is_counted: bool) -> Result<(), Errors> {
+        encode_to_vec(&mut self.encoder, s, &mut self.wbuf);
+        let is_bytes_exceeded = match self.max_bytes {
+            Some(max_bytes) => {
+                self.writtenbytes > self.header.len() as u64
+                    && self.writtenbytes + self.wbuf.len() as u64 > max_bytes
+            }
+            None => false,
+        };
+        if self.output_file.is_none()
+            || self.is_roll_requested
+            || self.availablerecords == 0
+            || is_bytes_exceeded
+        {
+            self.roll()?;
+        }
+        if let Some(max_bytes) = self.max_bytes {
+            if self.wbuf.len() as u64 > max_bytes {
+                eprintln!(
+                    "warning: a record of {} bytes is larger than max-bytes({})",
+                    self.wbuf.len(),
+                    max_bytes
+                );
+            }
+        }
+        if let Some(output_file) = &mut self.output_file {
+            output_file
+                .write_all(&self.wbuf)
+                .map_err(|e| Errors::from_io(&e, "writing to output file"))?;
+        }
+        self.writtenbytes += self.wbuf.len() as u64;
+        self.wbuf.clear();
+        if is_counted {
+            self.availablerecords = self.availablerecords.saturating_sub(1);
+        }
+        Ok(())
+    }
+    fn roll(&mut self) -> Result<(), Errors> {
+        if let Some(output_file) = self.output_file.take() {
+            partfile::commit_buffered(output_file, false)?;
+        }
+        let output_file = if self.is_temporary {
+            let mut output_file = partfile::open_part(
+                &mut self.current_suffix,
+                &self.prefix,
+                &mut self.output_file_path,
+                self.is_numerical_suffix,
+                &self.extra_suffix,
+            )?;
+            output_file
+                .write_all(&self.header)
+                .map_err(|e| Errors::from_io(&e, "writing header"))?;
+            self.availablerecords = self.max_records;
+            output_file
+        } else {
+            let output_file = rolling_file(
+                &mut self.current_suffix,
+                &self.prefix,
+                &mut self.output_file_path,
+                &mut self.availablerecords,
+                self.max_records,
+                self.is_numerical_suffix,
+                &self.extra_suffix,
+                &self.header,
+            )?;
+            partfile::PartFile::from_file(output_file, &self.output_file_path)
+        };
+        self.output_file = Some(std::io::BufWriter::with_capacity(
+            DEFAULT_BUFFER_SIZE,
+            output_file,
+        ));
+        self.writtenbytes = self.header.len() as u64;
+        self.is_roll_requested = false;
+        Ok(())
+    }
+    /// creates the first part if no record was written
+    pub fn finish(&mut self) -> Result<(), Errors> {
+        if self.output_file.is_none() {
+            self.roll()?;
+        }
+        match self.output_file.take() {
+            Some(output_file) => partfile::commit_buffered(output_file, false),
+            None => Ok(()),
+        }
+    }
+}
+
+/// output files per partition key, at most max_open_files are kept open
+/// and least recently used one is closed and reopened in append mode later.
+struct PartitionWriter {
+    output_directory: std::path::PathBuf,
+    prefix: String,
+    extra_suffix: String,
+    encoder: Encoder,
+    header: Vec<u8>,
+    max_open_files: usize,
+    open_files: HashMap<String, (std::io::BufWriter<std::fs::File>, u64)>,
+    created: HashSet<String>,
+    /// lowercased key and the first key, keys differing only in case share a file
+    folded_keys: HashMap<String, String>,
+    merged_keys: HashSet<String>,
+    counter: u64,
+    wbuf: Vec<u8>,
+}
+
+impl PartitionWriter {
+    pub fn new(
+        output_directory: std::path::PathBuf,
+        prefix: Option<&str>,
+        extra_suffix: Option<&str>,
+        encoder: Encoder,
+        max_open_files: usize,
+    ) -> Self {
+        PartitionWriter {
+            output_directory,
+            prefix: prefix.unwrap_or_default().to_owned(),
+            extra_suffix: extra_suffix.unwrap_or_default().to_owned(),
+            encoder,
+            header: Vec::new(),
+            max_open_files: std::cmp::max(max_open_files, 1),
+            open_files: HashMap::new(),
+            created: HashSet::new(),
+            folded_keys: HashMap::new(),
+            merged_keys: HashSet::new(),
+            counter: 0,
+            wbuf: Vec::with_capacity(4096),
+        }
+    }
+    /// header is written at the top of every file created after this call
+    pub fn write_header(&mut self, s: &str) -> Result<(), Errors> {
+        encode_to_vec(&mut self.encoder, s, &mut self.header);
+        Ok(())
+    }
+    /// key must be usable as a part of file name.
+    /// keys differing only in case are the same file on case-insensitive file systems,
+    /// so records of such keys are written to the file of the first key instead of truncating it.
+    fn get_file(&mut self, key: &str) -> Result<&mut std::io::BufWriter<std::fs::File>, Errors> {
+        let key = match self.folded_keys.entry(key.to_lowercase()) {
+            std::collections::hash_map::Entry::Occupied(v) => {
+                if v.get() != key && self.merged_keys.insert(key.to_owned()) {
+                    eprintln!(
+                        "warning: {} differs from {} only in case, records are written to {}",
+                        key,
+                        v.get(),
+                        v.get()
+                    );
+                }
+                v.get().clone()
+            }
+            std::collections::hash_map::Entry::Vacant(v) => v.insert(key.to_owned()).clone(),
+        };
+        let key = key.as_str();
+        self.counter += 1;
+        if !self.open_files.contains_key(key) {
+            if self.open_files.len() >= self.max_open_files {
+                let lru = self
+                    .open_files
+                    .iter()
+                    .min_by_key(|(_, (_, used))| *used)
+                    .map(|(k, _)| k.clone());
+                if let Some((mut f, _)) = lru.and_then(|lru| self.open_files.remove(&lru)) {
+                    flush_part(&mut f)?;
+                }
+            }
+            let mut output_file_path = self.output_directory.clone();
+            output_file_path.push(format!("{}{}{}", self.prefix, key, self.extra_suffix));
+            let is_created = self.created.contains(key);
+            let mut f = std::fs::OpenOptions::new()
+                .create(true)
+                .write(true)
+                .append(is_created)
+                .truncate(!is_created)
+                .open(&output_file_path)
+                .map_err(|e| Errors::from_io(&e, "in opening file"))?;
+            if !is_created {
+                f.write_all(&self.header)
+                    .map_err(|e| Errors::from_io(&e, "writing header"))?;
+                self.created.insert(key.to_owned());
+            }
+            self.open_files
+                .insert(key.to_owned(), (std::io::BufWriter::new(f), self.counter));
+        }
+        let (f, used) = self.open_files.get_mut(key).unwrap();
+        *used = self.counter;
+        Ok(f)
+    }
+    pub fn write_record(&mut self, key: &str, s: &str) -> Result<(), Errors> {
+        let mut wbuf = std::mem::take(&mut self.wbuf);
+        encode_to_vec(&mut self.encoder, s, &mut wbuf);
+        let f = self.get_file(key)?;
+        f.write_all(&wbuf)
+            .map_err(|e| Errors::from_io(&e, "writing to output file"))?;
+        wbuf.clear();
+        self.wbuf = wbuf;
+        Ok(())
+    }
+    pub fn finish(&mut self) -> Result<(), Errors> {
+        for (f, _) in self.open_files.values_mut() {
+            flush_part(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// concatenates lines of a record, every line is wrapped if max-chars is given
+fn join_record_lines(lines: Vec<(String, bool)>, opts: &LineOptions) -> String {
+    let mut record = String::new();
+    for (line, _) in lines {
+        match opts.max_chars {
+            Some(max_chars) => {
+                record.push_str(&wrap_line(&line, max_chars, opts).join(opts.line_ending()))
+            }
+            None => record.push_str(&line),
+        }
+    }
+    record
+}
+
+/// writes lines of a record to writer.
+/// in record mode, the whole record is counted as one and never split into different files,
+/// otherwise every line(or wrapped piece of line) is counted.
+fn write_text_record(
+    writer: &mut TextPartWriter,
+    lines: Vec<(String, bool)>,
+    opts: &LineOptions,
+    prefix_template: &str,
+) -> Result<(), Errors> {
+    let (first_line, last_line) = match (lines.first(), lines.last()) {
+        (Some((first, _)), Some((last, is_last_newline))) => (
+            split_line_ending(first).0.to_owned(),
+            (split_line_ending(last).0.to_owned(), *is_last_newline),
+        ),
+        _ => return Ok(()),
+    };
+    if let Some(caps) = opts
+        .split_before
+        .as_ref()
+        .and_then(|re| re.captures(&first_line))
+    {
+        writer.start_next_part(Some(expand_name_template(prefix_template, Some(&caps))));
+    }
+    if opts.is_record_mode() {
+        writer.write_record(&join_record_lines(lines, opts), last_line.1)?;
+    } else {
+        for (line, is_last_newline) in lines {
+            let mut pieces = match opts.max_chars {
+                Some(max_chars) => wrap_line(&line, max_chars, opts),
+                None => vec![line],
+            };
+            let last_piece = pieces.pop().unwrap_or_default();
+            for mut piece in pieces {
+                piece.push_str(opts.line_ending());
+                writer.write_record(&piece, true)?;
+            }
+            writer.write_record(&last_piece, is_last_newline)?;
+        }
+    }
+    if let Some(caps) = opts
+        .split_after
+        .as_ref()
+        .and_then(|re| re.captures(&last_line.0))
+    {
+        writer.start_next_part(Some(expand_name_template(prefix_template, Some(&caps))));
+    }
+    Ok(())
+}
+
+/// opens input of text splitting, mapped into memory with --mmap if input is a regular file
+fn open_line_reader(opts: &LineOptions, decoder: Decoder) -> Result<LineReader, Errors> {
+    let mapping = if opts.is_mmap {
+        mmap::map_input(&opts.input)?
+    } else {
+        None
+    };
+    let input: Box<dyn Read> = match mapping {
+        Some(_) => Box::new(std::io::empty()),
+        None => get_file_or_stdin(&opts.input)?,
+    };
+    Ok(LineReader::new(input, decoder)
+        .with_mapping(mapping)
+        .with_zero_terminated(opts.is_zero_terminated)
+        .with_buffer_size(opts.buffer_size))
+}
+
+fn split_text_encoding(opts: &LineOptions) -> Result<(), Errors> {
+    if let Some(time_window) = &opts.time_window {
+        return timewindow::split_text_by_time(opts, time_window);
+    }
+    let (decoder, encoder) = get_text_codec(&opts.encoding)?;
+    let output_directory = get_output_directory(&opts.output)?;
+    let mut reader = open_line_reader(opts, decoder)?;
+    let prefix_template = opts.prefix.as_deref().unwrap_or("x");
+    let mut writer = TextPartWriter::new(
+        output_directory,
+        Some(&expand_name_template(prefix_template, None)),
+        opts.extra_suffix.as_deref(),
+        opts.is_numerical_suffix,
+        encoder,
+        opts.max_lines,
+        opts.max_bytes,
+    );
+    let mut headerlines = 0u64;
+    let mut record_lines: Vec<(String, bool)> = Vec::new();
+    let mut is_after_empty = false;
+    while let Some(lines) = reader.read_lines()? {
+        for (line, is_last_newline) in lines {
+            if headerlines < opts.header_lines {
+                writer.write_header(&line)?;
+                if is_last_newline {
+                    headerlines += 1;
+                }
+                continue;
+            }
+            let text = split_line_ending(&line).0;
+            let is_record_start = match &opts.record_start {
+                // lines not matching record-start are continuation of previous record
+                Some(re) => re.is_match(text),
+                // empty lines belong to the previous paragraph, and leading ones to the first
+                None if opts.is_paragraph => {
+                    !text.is_empty()
+                        && is_after_empty
+                        && record_lines
+                            .iter()
+                            .any(|(v, _)| !split_line_ending(v).0.is_empty())
+                }
+                None => true,
+            };
+            if is_record_start && !record_lines.is_empty() {
+                let record = std::mem::take(&mut record_lines);
+                write_text_record(&mut writer, record, opts, prefix_template)?;
+            }
+            is_after_empty = text.is_empty();
+            record_lines.push((line, is_last_newline));
+        }
+    }
+    write_text_record(&mut writer, record_lines, opts, prefix_template)?;
+    writer.finish()
+}
+
+fn split_binary(opts: &BinaryOptions) -> Result<(), Errors> {
+    if let Some(framing) = opts.framing {
+        return framing::split_binary_frames(opts, framing);
+    }
+    if let Some(cdc) = &opts.cdc {
+        return cdc::split_binary_cdc(opts, cdc);
+    }
+    // length of a regular file is checked before splitting, so no part is written for a wrong input
+    let regular_file = parallel::open_regular_file(&opts.input)?;
+    if let Some((_, length)) = &regular_file {
+        check_partial_record(opts, *length)?;
+    }
+    if let (Some(store), Some(index)) = (&opts.store, &opts.index) {
+        let mut store = store::ChunkStore::new(store, index, opts.is_sync)?;
+        let totalbytes = store::split_binary_store(opts, &mut store)?;
+        if regular_file.is_none() {
+            // the last chunk is not finished if the check fails
+            check_partial_record(opts, totalbytes)?;
+        }
+        return store.finish();
+    }
+    if let Some(only) = opts.only {
+        return extract::split_binary_only(opts, only);
+    }
+    if opts.is_mmap {
+        if let Some(mapping) = mmap::map_input(&opts.input)? {
+            return mmap::split_binary_mmap(opts, &mapping);
+        }
+    }
+    let mut journal = if opts.is_resume {
+        Some(resume::Journal::open(
+            opts,
+            regular_file.as_ref().map(|(_, length)| *length),
+        )?)
+    } else {
+        None
+    };
+    // stdin and other inputs than regular file are split by the loop below
+    if let Some((input, length)) = regular_file {
+        if opts.jobs > 1 {
+            parallel::split_binary_parallel(opts, input, length, opts.jobs)?;
+        } else {
+            zerocopy::split_binary_file(opts, input, length, journal.as_mut())?;
+        }
+        return journal.map_or(Ok(()), resume::Journal::finish);
+    }
+    let mut input = get_file_or_stdin(&opts.input)?;
+    if journal.as_ref().map(|v| v.is_done) == Some(true) {
+        // every part is already completed
+        return journal.map_or(Ok(()), resume::Journal::finish);
+    }
+    let (mut current_suffix, start) = match &journal {
+        Some(v) => (v.suffix.clone(), v.offset),
+        None => (String::new(), 0),
+    };
+    let mut totalbytes = 0u64;
+    if start > 0 {
+        // input is read through since it can not be seeked
+        totalbytes = std::io::copy(&mut (&mut input).take(start), &mut std::io::sink())
+            .map_err(|e| Errors::from_io(&e, "reading from input file"))?;
+        if totalbytes < start {
+            return Err(resume::input_shorter_error(start, totalbytes));
+        }
+    }
+    let buffer_size = opts.buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE);
+    let mut buf = vec![0; buffer_size];
+    let output_directory = match &opts.output {
+        Some(v) => std::path::PathBuf::from(v),
+        None => {
+            std::env::current_dir().map_err(|e| Errors::from_io(&e, "getting current directory"))?
+        }
+    };
+    ensure_dir(&output_directory)?;
+    let prefix = opts.prefix.clone().unwrap_or(String::from("x"));
+    let mut output_file_path = output_directory.to_path_buf();
+    output_file_path.push(format!("{}.{}", prefix, ""));
+    let extra_suffix = opts.extra_suffix.clone().unwrap_or_default();
+    // the first part is created even for empty input, parts after it only when data arrives
+    let mut output_file = if start == 0 {
+        Some(partfile::open_part(
+            &mut current_suffix,
+            &prefix,
+            &mut output_file_path,
+            opts.is_numerical_suffix,
+            &extra_suffix,
+        )?)
+    } else {
+        None
+    };
+    let mut available = opts.max_size;
+    loop {
+        let bytesread = input
+            .read(&mut buf)
+            .map_err(|e| Errors::from_io(&e, "reading from input file"))?;
+        if bytesread == 0 {
+            break;
+        }
+        totalbytes += bytesread as u64;
+        let mut remaining = bytesread;
+        let mut offset = 0usize;
+        while remaining > 0 {
+            if available == 0 {
+                if let Some(part) = output_file.take() {
+                    resume::commit_part(part, opts.max_size, opts.is_sync, journal.as_mut())?;
+                }
+                available = opts.max_size;
+            }
+            if output_file.is_none() {
+                output_file = Some(partfile::open_part(
                     &mut current_suffix,
                     &prefix,
                     &mut output_file_path,
-                    &mut available,
-                    opts.max_size,
                     opts.is_numerical_suffix,
                     &extra_suffix,
-                )?;
-                output_file = next_output_file;
+                )?);
                 eprintln!("next suffix = {}", current_suffix);
             }
+            let bytesavailable = std::cmp::min(remaining as u64, available) as usize;
+            if let Some(part) = &mut output_file {
+                part.write_all(&buf[offset..offset + bytesavailable])
+                    .map_err(|e| Errors::from_io(&e, "writing output file"))?;
+            }
+            if let Some(journal) = &mut journal {
+                journal.update(&buf[offset..offset + bytesavailable]);
+            }
+            remaining -= bytesavailable;
+            available -= bytesavailable as u64;
+            offset += bytesavailable;
+        }
+    }
+    // length of stdin is known only at the end, the last part is dropped if it is wrong
+    check_partial_record(opts, totalbytes)?;
+    if let Some(part) = output_file {
+        resume::commit_part(
+            part,
+            opts.max_size - available,
+            opts.is_sync,
+            journal.as_mut(),
+        )?;
+    }
+    journal.map_or(Ok(()), resume::Journal::finish)
+}
+
+/// warns or fails if input length is not a multiple of record-size
+fn check_partial_record(opts: &BinaryOptions, totalbytes: u64) -> Result<(), Errors> {
+    if let Some(record_size) = opts.record_size {
+        let partial = totalbytes % record_size;
+        if partial != 0 {
+            let message = format!(
+                "input length({}) is not a multiple of record-size({}), the last record has only {} bytes",
+                totalbytes, record_size, partial
+            );
+            if opts.is_partial_record_error {
+                return Err(Errors::Io(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    message,
+                )));
+            }
+            eprintln!("warning: {}", message);
         }
     }
     Ok(())
@@ -666,10 +1757,20 @@ fn create_buffer_size_option<'a, 'b>() -> Arg<'a, 'b> {
     Arg::with_name("buffer-size")
         .long("buffer-size")
         .takes_value(true)
-        .help("buffer size")
-        .default_value("1024")
+        .help("buffer size in bytes(default: 1048576)")
 }
 
+fn create_mmap_option<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("mmap")
+        .long("mmap")
+        .help("map input file into memory instead of reading it, ignored for stdin, pipes and special files")
+}
+
+fn create_verbose_option<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("verbose").short("v").long("verbose").help(
+        "report how data of every file is copied(copy_file_range, sendfile, buffered, mmap or hole)",
+    )
+}
 fn create_binary_subcommand<'a, 'b>() -> App<'a, 'b> {
     SubCommand::with_name("binary")
         .alias("b")
@@ -677,8 +1778,116 @@ fn create_binary_subcommand<'a, 'b>() -> App<'a, 'b> {
         .arg(
             Arg::with_name("max-size")
                 .alias("m")
-                .required(true)
-                .help("max binary size of splitted binary"),
+                .required_unless_one(&["max-records", "cdc"])
+                .help(
+                    "max binary size of splitted binary(rounded down to a multiple of record-size)",
+                ),
+        )
+        .arg(
+            Arg::with_name("record-size")
+                .long("record-size")
+                .takes_value(true)
+                .help("size of fixed-length records, files are cut only on multiples of it"),
+        )
+        .arg(
+            Arg::with_name("max-records")
+                .long("max-records")
+                .takes_value(true)
+                .requires("record-size")
+                .conflicts_with("max-size")
+                .help("max number of records per file"),
+        )
+        .arg(
+            Arg::with_name("partial-record")
+                .long("partial-record")
+                .takes_value(true)
+                .possible_values(&["warn", "error"])
+                .requires("record-size")
+                .help(
+                    "what to do when input length is not a multiple of record-size(default: warn)",
+                ),
+        )
+        .arg(
+            Arg::with_name("framing")
+                .long("framing")
+                .takes_value(true)
+                .possible_values(framing::Framing::VALUES)
+                .conflicts_with_all(&["record-size", "max-records"])
+                .help("input is length-prefixed frames(<length><payload>), files are cut only between complete frames"),
+        )
+        .arg(
+            Arg::with_name("cdc")
+                .long("cdc")
+                .takes_value(true)
+                .possible_values(&["fastcdc"])
+                .conflicts_with_all(&["max-size", "record-size", "max-records", "framing"])
+                .help("split at content-defined boundaries, so a slightly modified input yields mostly identical files"),
+        )
+        .arg(
+            Arg::with_name("cdc-min")
+                .long("min")
+                .takes_value(true)
+                .requires("cdc")
+                .help("min chunk size of cdc(default: avg / 4)"),
+        )
+        .arg(
+            Arg::with_name("cdc-avg")
+                .long("avg")
+                .takes_value(true)
+                .requires("cdc")
+                .help("average chunk size of cdc(default: 65536)"),
+        )
+        .arg(
+            Arg::with_name("cdc-max")
+                .long("max")
+                .takes_value(true)
+                .requires("cdc")
+                .help("max chunk size of cdc(default: avg * 4)"),
+        )
+        .arg(
+            Arg::with_name("manifest")
+                .long("manifest")
+                .takes_value(true)
+                .requires("cdc")
+                .help("write sha256, offset, length and file name of every chunk to this file(tab separated)"),
+        )
+        .arg(
+            Arg::with_name("compare")
+                .long("compare")
+                .takes_value(true)
+                .requires("cdc")
+                .help("report how many chunks are unchanged compared to a previous manifest or directory of chunks"),
+        )
+        .arg(create_verbose_option())
+        .arg(
+            Arg::with_name("jobs")
+                .short("j")
+                .long("jobs")
+                .takes_value(true)
+                .conflicts_with_all(&["framing", "cdc", "store", "only"])
+                .help("number of threads writing parts concurrently, used only if input is a regular file(default: 1)"),
+        )
+        .arg(
+            Arg::with_name("only")
+                .long("only")
+                .takes_value(true)
+                .conflicts_with_all(&["framing", "cdc", "store"])
+                .help("write only the K-th part(0-based, named as in full split), input file is seeked to the part"),
+        )
+        .arg(
+            Arg::with_name("store")
+                .long("store")
+                .takes_value(true)
+                .requires("index")
+                .conflicts_with("framing")
+                .help("write chunks to this directory under their sha256(\"ab/cdef...\") instead of output files, chunks already stored are skipped"),
+        )
+        .arg(
+            Arg::with_name("index")
+                .long("index")
+                .takes_value(true)
+                .requires("store")
+                .help("index file listing sha256 and length of chunks in order, used by `combine --index`"),
         )
         .arg(create_input_option())
         .arg(create_output_option())
@@ -686,6 +1895,18 @@ fn create_binary_subcommand<'a, 'b>() -> App<'a, 'b> {
         .arg(create_numeric_suffix_option())
         .arg(create_extra_suffix_option())
         .arg(create_buffer_size_option())
+        .arg(create_mmap_option().conflicts_with_all(&["framing", "cdc", "store", "only", "jobs"]))
+        .arg(
+            Arg::with_name("sync")
+                .long("sync")
+                .help("fsync every part before renaming it to its name"),
+        )
+        .arg(
+            Arg::with_name("resume")
+                .long("resume")
+                .conflicts_with_all(&["framing", "cdc", "store", "only", "jobs", "mmap"])
+                .help("record completed parts in a journal in the output folder, and continue an interrupted split from the first part not matching the journal(only for the same input and max-size)"),
+        )
 }
 
 fn create_text_subcommand<'a, 'b>() -> App<'a, 'b> {
@@ -695,15 +1916,121 @@ fn create_text_subcommand<'a, 'b>() -> App<'a, 'b> {
         .arg(
             Arg::with_name("max-lines")
                 .alias("m")
-                .required(true)
+                .required_unless_one(&["max-bytes", "split-before", "split-after", "time-window"])
                 .help("max line number per file"),
         )
+        .arg(
+            Arg::with_name("max-bytes")
+                .long("max-bytes")
+                .takes_value(true)
+                .help("max encoded bytes per file, lines are never cut(used with max-lines, whichever is reached first)"),
+        )
+        .arg(
+            Arg::with_name("split-before")
+                .long("split-before")
+                .takes_value(true)
+                .help("start a new file at lines matching this regular expression, the matched line is the first line of the new file")
+                .long_help("start a new file at lines matching this regular expression, the matched line is the first line of the new file.\n\
+                    \"$1\", \"${1}\" or \"${name}\" in prefix is replaced with the capture group of the matched line\n\
+                    (with --split-after, it names the file started after the matched line)"),
+        )
+        .arg(
+            Arg::with_name("split-after")
+                .long("split-after")
+                .takes_value(true)
+                .help("start a new file after lines matching this regular expression, the matched line is the last line of the file"),
+        )
+        .arg(
+            Arg::with_name("record-start")
+                .long("record-start")
+                .takes_value(true)
+                .help("lines matching this regular expression start a record and other lines are continuation of the record, max-lines counts records and a record is never split"),
+        )
+        .arg(
+            Arg::with_name("zero-terminated")
+                .short("z")
+                .long("zero-terminated")
+                .help("lines are terminated by NUL instead of line endings"),
+        )
+        .arg(
+            Arg::with_name("paragraph")
+                .long("paragraph")
+                .conflicts_with("record-start")
+                .help("blocks separated by one or more empty lines are records, max-lines counts paragraphs and a paragraph is never split"),
+        )
+        .arg(
+            Arg::with_name("time-window")
+                .long("time-window")
+                .takes_value(true)
+                .conflicts_with_all(&["max-lines", "max-bytes", "split-before", "split-after", "paragraph"])
+                .help("split by time window of timestamps like \"30s\", \"15m\", \"1h\" or \"1d\", parts are named by the start of the window")
+                .long_help("split by time window of timestamps like \"30s\", \"15m\", \"1h\" or \"1d\", parts are named by the start of the window(\"{prefix}2026-10-17T13{extra-suffix}\").\n\
+                    a line with timestamp starts a record and lines without timestamp stay with the previous record,\n\
+                    lines before the first timestamp go to the first part(\"{prefix}unknown{extra-suffix}\" if no timestamp is found)"),
+        )
+        .arg(
+            Arg::with_name("time-format")
+                .long("time-format")
+                .takes_value(true)
+                .requires("time-window")
+                .help("strftime format of timestamp, parsed at the beginning of the line unless time-pattern is given(default: \"%Y-%m-%dT%H:%M:%S\")"),
+        )
+        .arg(
+            Arg::with_name("time-pattern")
+                .long("time-pattern")
+                .takes_value(true)
+                .requires("time-window")
+                .help("regular expression to find timestamp in the line, the first capture group(or whole match) is parsed with time-format"),
+        )
+        .arg(
+            Arg::with_name("time-name-format")
+                .long("time-name-format")
+                .takes_value(true)
+                .requires("time-window")
+                .help("strftime format of window start in part names(default: depends on time-window, \"%Y-%m-%dT%H\" for hours)"),
+        )
+        .arg(
+            Arg::with_name("out-of-order")
+                .long("out-of-order")
+                .takes_value(true)
+                .possible_values(&["current", "append", "error"])
+                .requires("time-window")
+                .help("records earlier than the current window are kept in the current part, appended to the part of their window, or treated as error(default: current)"),
+        )
+        .arg(
+            Arg::with_name("header-lines")
+                .long("header-lines")
+                .takes_value(true)
+                .help("number of header lines which are written at the top of every file(not counted in max-lines)"),
+        )
         .arg(
             Arg::with_name("max-chars")
                 .long("max-chars")
                 .takes_value(true)
                 .help("max characters per line"),
         )
+        .arg(
+            Arg::with_name("wrap-unit")
+                .long("wrap-unit")
+                .takes_value(true)
+                .possible_values(&["chars", "graphemes", "width", "bytes"])
+                .help("unit of max-chars(chars, graphemes, display width or utf-8 bytes, default: chars)"),
+        )
+        .arg(
+            Arg::with_name("wrap")
+                .long("wrap")
+                .takes_value(true)
+                .possible_values(&["hard", "words"])
+                .requires("max-chars")
+                .help("how to wrap lines longer than max-chars(hard: cut at the limit, words: cut at the last word boundary, default: hard)"),
+        )
+        .arg(
+            Arg::with_name("continuation-prefix")
+                .long("continuation-prefix")
+                .takes_value(true)
+                .requires("max-chars")
+                .help("prefix added to lines continued from a wrapped line"),
+        )
         .arg(
             Arg::with_name("encoding")
                 .short("e")
@@ -716,6 +2043,8 @@ fn create_text_subcommand<'a, 'b>() -> App<'a, 'b> {
         .arg(create_prefix_option())
         .arg(create_numeric_suffix_option())
         .arg(create_extra_suffix_option())
+        .arg(create_buffer_size_option())
+        .arg(create_mmap_option())
 }
 
 fn create_combine_subcommand<'a, 'b>() -> App<'a, 'b> {
@@ -735,11 +2064,34 @@ fn create_combine_subcommand<'a, 'b>() -> App<'a, 'b> {
                 .multiple(true)
                 .long_help("input files(if empty, read file list from stdin, glob pattern is OK)"),
         )
+        .arg(
+            Arg::with_name("zero-terminated")
+                .short("z")
+                .long("zero-terminated")
+                .long_help("file list from stdin is separated by NUL(like `find -print0`)"),
+        )
+        .arg(
+            Arg::with_name("index")
+                .long("index")
+                .takes_value(true)
+                .requires("store")
+                .conflicts_with_all(&["input", "zero-terminated"])
+                .long_help("rebuild from index file written by `binary --store --index`"),
+        )
+        .arg(
+            Arg::with_name("store")
+                .long("store")
+                .takes_value(true)
+                .requires("index")
+                .long_help("chunk store directory of index"),
+        )
+        .arg(create_verbose_option())
+        .arg(create_buffer_size_option())
         .arg(
             Arg::with_name("notruncate")
                 .long("notruncate")
                 .long_help("no truncate when file already exists")
-                .takes_value(false)
+                .takes_value(false),
         )
 }
 
@@ -747,6 +2099,11 @@ struct CombineBinaryOptions {
     pub paths: Vec<String>,
     pub output: Option<String>,
     pub no_truncate: bool,
+    pub is_zero_terminated: bool,
+    pub index: Option<String>,
+    pub store: Option<String>,
+    pub is_verbose: bool,
+    pub buffer_size: Option<usize>,
 }
 
 impl CombineBinaryOptions {
@@ -756,28 +2113,44 @@ impl CombineBinaryOptions {
             None => Vec::new(),
         };
         Ok(CombineBinaryOptions {
-            paths: paths,
-            output: matches.value_of("output").and_then(|x| Some(x.to_owned())),
+            paths,
+            output: matches.value_of("output").map(|x| x.to_owned()),
             no_truncate: matches.is_present("notruncate"),
+            is_zero_terminated: matches.is_present("zero-terminated"),
+            index: matches.value_of("index").map(|x| x.to_owned()),
+            store: matches.value_of("store").map(|x| x.to_owned()),
+            is_verbose: matches.is_present("verbose"),
+            buffer_size: parse_buffer_size(matches.value_of("buffer-size"))?,
         })
     }
 }
 
 fn get_stdout_or_file(path: &Option<String>, no_truncate: bool) -> Result<StdoutOrFile, Errors> {
     if let Some(s) = path {
-        match std::fs::OpenOptions::new().create(true).truncate(!no_truncate).write(true).open(s) {
+        match std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(!no_truncate)
+            .write(true)
+            .open(s)
+        {
             Ok(f) => Ok(StdoutOrFile::File(f)),
             Err(e) => Err(Errors::from_io(&e, "failed to create output file")),
         }
     } else {
-        Ok(StdoutOrFile::Stdout(std::io::stdout()))
+        Ok(StdoutOrFile::Stdout(std::io::BufWriter::with_capacity(
+            DEFAULT_BUFFER_SIZE,
+            std::io::stdout(),
+        )))
     }
 }
 
-fn transfer_file_content<W>(p: &std::path::Path, output: &mut W) -> Result<(), Errors>
-where
-    W: std::io::Write,
-{
+fn transfer_file_content(
+    p: &std::path::Path,
+    output: &mut StdoutOrFile,
+    buf: &mut [u8],
+    is_sparse: bool,
+    is_verbose: bool,
+) -> Result<(), Errors> {
     let mut f = match std::fs::File::open(p) {
         Ok(v) => v,
         Err(e) => {
@@ -787,30 +2160,41 @@ where
             ))
         }
     };
-    copy_content(&mut f, output)?;
+    match output {
+        // file to file is copied in kernel if possible
+        StdoutOrFile::File(output) => {
+            let length = match f.metadata() {
+                Ok(v) if is_sparse && v.is_file() => Some(v.len()),
+                _ => None,
+            };
+            let (copied, method) = match length {
+                // holes are seeked over, and blocks of zeros too if data is copied through buf
+                Some(length) => sparse::copy_sparse(&mut f, output, 0, length, buf, true)?,
+                None => zerocopy::copy_between_files(&mut f, output, u64::MAX, buf, false)?,
+            };
+            if is_verbose {
+                eprintln!("{}: {} bytes by {}", p.display(), copied, method);
+            }
+        }
+        StdoutOrFile::Stdout(_) => copy_content(&mut f, output, buf)?,
+    }
     Ok(())
 }
 
-fn copy_content<R, W>(io_in: &mut R, io_out: &mut W) -> Result<(), Errors>
+fn copy_content<R, W>(io_in: &mut R, io_out: &mut W, buf: &mut [u8]) -> Result<(), Errors>
 where
     R: std::io::Read,
     W: std::io::Write,
 {
-    let mut buf = [0u8; 4096];
     loop {
-        let bytesread = match io_in.read(&mut buf) {
+        let bytesread = match io_in.read(buf) {
             Ok(v) => v,
-            Err(e) => {
-                return Err(Errors::from_io(
-                    &e,
-                    format!("failed to raed source file content").as_str(),
-                ))
-            }
+            Err(e) => return Err(Errors::from_io(&e, "failed to read source file content")),
         };
         if bytesread == 0 {
             break;
         }
-        match io_out.write(&buf[0..bytesread]) {
+        match io_out.write_all(&buf[0..bytesread]) {
             Ok(_) => (),
             Err(e) => return Err(Errors::from_io(&e, "failed to write to output")),
         };
@@ -818,32 +2202,75 @@ where
     Ok(())
 }
 
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> std::path::PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    std::path::PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> std::path::PathBuf {
+    std::path::PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
 fn combine_binaries(opts: &CombineBinaryOptions) -> Result<(), Errors> {
-    let mut output = match get_stdout_or_file(&opts.output, opts.no_truncate) {
-        Ok(v) => v,
-        Err(e) => return Err(e),
+    let mut output = get_stdout_or_file(&opts.output, opts.no_truncate)?;
+    let mut buf = vec![0u8; opts.buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE)];
+    // holes can be left only in a new regular file, existing content must be overwritten
+    let is_sparse = match &output {
+        StdoutOrFile::File(f) => {
+            !opts.no_truncate && f.metadata().map(|v| v.is_file()).unwrap_or(false)
+        }
+        StdoutOrFile::Stdout(_) => false,
     };
-    if opts.paths.is_empty() {
-        let sin = std::io::stdin();
-        let mut pathbuf = String::new();
+    if let (Some(index), Some(store)) = (&opts.index, &opts.store) {
+        store::combine_from_store(index, store, &mut output, &mut buf)?;
+    } else if opts.paths.is_empty() {
+        let mut sin = std::io::stdin().lock();
+        let delimiter = if opts.is_zero_terminated {
+            b'\0'
+        } else {
+            b'\n'
+        };
+        let mut pathbuf: Vec<u8> = Vec::new();
         loop {
-            match sin.read_line(&mut pathbuf) {
-                Ok(v) => v,
+            pathbuf.clear();
+            match sin.read_until(delimiter, &mut pathbuf) {
+                Ok(0) => break,
+                Ok(_) => (),
                 Err(e) => return Err(Errors::from_io(&e, "failed to source file path from stdin")),
             };
+            // only the terminator is removed, spaces are part of the path
+            if pathbuf.last() == Some(&delimiter) {
+                pathbuf.pop();
+            }
+            if !opts.is_zero_terminated && pathbuf.last() == Some(&b'\r') {
+                pathbuf.pop();
+            }
             if pathbuf.is_empty() {
-                break;
+                continue;
             }
-            transfer_file_content(std::path::Path::new(&pathbuf.trim()), &mut output)?;
-            pathbuf.clear();
+            transfer_file_content(
+                &path_from_bytes(&pathbuf),
+                &mut output,
+                &mut buf,
+                is_sparse,
+                opts.is_verbose,
+            )?;
         }
     } else {
         for pathpattern in opts.paths.iter() {
-            for entry in glob::glob(pathpattern).map_err(|e| Errors::Pattern(e))? {
+            for entry in glob::glob(pathpattern).map_err(Errors::Pattern)? {
                 match entry {
                     Ok(v) => {
-                        transfer_file_content(&v, &mut output)?;
-                    },
+                        transfer_file_content(
+                            &v,
+                            &mut output,
+                            &mut buf,
+                            is_sparse,
+                            opts.is_verbose,
+                        )?;
+                    }
                     Err(e) => {
                         return Err(Errors::Glob(e));
                     }
@@ -852,7 +2279,9 @@ fn combine_binaries(opts: &CombineBinaryOptions) -> Result<(), Errors> {
             }
         }
     }
-    Ok(())
+    output
+        .flush()
+        .map_err(|e| Errors::from_io(&e, "failed to write to output"))
 }
 
 fn main() -> Result<(), Errors> {
@@ -862,18 +2291,34 @@ fn main() -> Result<(), Errors> {
         .about("binary/text splitter")
         .subcommand(create_binary_subcommand())
         .subcommand(create_text_subcommand())
-        .subcommand(create_combine_subcommand());
+        .subcommand(create_combine_subcommand())
+        .subcommand(extract::create_extract_subcommand())
+        .subcommand(csv::create_csv_subcommand())
+        .subcommand(json::create_ndjson_subcommand())
+        .subcommand(json::create_json_subcommand());
     let matches = app.get_matches();
     if let Some(matches) = matches.subcommand_matches("text") {
         // process as text
         let opts = LineOptions::from_arg_matches(matches)?;
         split_text_encoding(&opts)?;
     } else if let Some(matches) = matches.subcommand_matches("binary") {
-        let opts = BinaryOptions::from_arg_matches(&matches)?;
+        let opts = BinaryOptions::from_arg_matches(matches)?;
         split_binary(&opts)?;
     } else if let Some(matches) = matches.subcommand_matches("combine") {
-        let opts = CombineBinaryOptions::from(&matches)?;
+        let opts = CombineBinaryOptions::from(matches)?;
         combine_binaries(&opts)?;
+    } else if let Some(matches) = matches.subcommand_matches("extract") {
+        let opts = extract::ExtractOptions::from_arg_matches(matches)?;
+        extract::extract_range(&opts)?;
+    } else if let Some(matches) = matches.subcommand_matches("csv") {
+        let opts = csv::CsvOptions::from_arg_matches(matches)?;
+        csv::split_csv(&opts)?;
+    } else if let Some(matches) = matches.subcommand_matches("ndjson") {
+        let opts = json::NdjsonOptions::from_arg_matches(matches)?;
+        json::split_ndjson(&opts)?;
+    } else if let Some(matches) = matches.subcommand_matches("json") {
+        let opts = json::JsonArrayOptions::from_arg_matches(matches)?;
+        json::split_json_array(&opts)?;
     } else {
         println!("{}", matches.usage());
         println!("`--help` for more details");
@@ -884,3 +2329,165 @@ fn main() -> Result<(), Errors> {
 
     // println!("Hello, world!");
 }
+
+/// empty directory for a test in the temporary directory, removed when it is dropped
+#[cfg(test)]
+pub(crate) struct TestDir(std::path::PathBuf);
+
+#[cfg(test)]
+impl TestDir {
+    pub fn new(name: &str) -> Self {
+        let mut path = std::env::temp_dir();
+        path.push(format!("bsp-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TestDir(path)
+    }
+    pub fn join(&self, name: &str) -> std::path::PathBuf {
+        self.0.join(name)
+    }
+    /// names of files in the directory, sorted
+    pub fn file_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(&self.0)
+            .unwrap()
+            .map(|v| v.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrap_line_hard() {
+        let opts = LineOptions::new(1);
+        assert_eq!(wrap_line("abcdefg\n", 3, &opts), vec!["abc", "def", "g\n"]);
+        // line ending is not measured
+        assert_eq!(wrap_line("abc\r\n", 3, &opts), vec!["abc\r\n"]);
+        assert_eq!(wrap_line("\n", 3, &opts), vec!["\n"]);
+    }
+
+    #[test]
+    fn wrap_line_width() {
+        let opts = LineOptions::new(1).with_wrap_unit(WrapUnit::Width);
+        assert_eq!(wrap_line("日本語\n", 4, &opts), vec!["日本", "語\n"]);
+        // a unit wider than max is not cut
+        assert_eq!(wrap_line("日本\n", 1, &opts), vec!["日", "本\n"]);
+    }
+
+    #[test]
+    fn wrap_line_graphemes() {
+        let opts = LineOptions::new(1).with_wrap_unit(WrapUnit::Graphemes);
+        assert_eq!(
+            wrap_line("e\u{301}e\u{301}e\u{301}\n", 2, &opts),
+            vec!["e\u{301}e\u{301}", "e\u{301}\n"]
+        );
+    }
+
+    #[test]
+    fn wrap_line_words() {
+        let opts = LineOptions::new(1).with_wrap_mode(WrapMode::Words);
+        let pieces = wrap_line("hello world foo\n", 8, &opts);
+        assert_eq!(pieces, vec!["hello ", "world ", "foo\n"]);
+        assert_eq!(pieces.concat(), "hello world foo\n");
+        // a word longer than max is cut
+        assert_eq!(
+            wrap_line("abcdefghij kl\n", 4, &opts),
+            vec!["abcd", "efgh", "ij ", "kl\n"]
+        );
+    }
+
+    #[test]
+    fn wrap_line_continuation_prefix() {
+        let opts = LineOptions::new(1).with_continuation_prefix(Some(">"));
+        // prefix is counted in max
+        assert_eq!(wrap_line("abcdefg\n", 4, &opts), vec!["abcd", ">efg\n"]);
+        assert_eq!(
+            wrap_line("abcdefghij\n", 4, &opts),
+            vec!["abcd", ">efg", ">hij\n"]
+        );
+    }
+
+    #[test]
+    fn sanitize_file_name_replaces_invalid_characters() {
+        assert_eq!(sanitize_file_name("a/b\\c:d"), "a_b_c_d");
+        assert_eq!(sanitize_file_name("*?\"<>|"), "______");
+        assert_eq!(sanitize_file_name("a\tb\n"), "a_b_");
+        assert_eq!(sanitize_file_name("日本"), "日本");
+    }
+
+    #[test]
+    fn sanitize_file_name_trailing_dots_and_spaces() {
+        assert_eq!(sanitize_file_name("a. "), "a__");
+        assert_eq!(sanitize_file_name(".."), "__");
+        assert_eq!(sanitize_file_name(""), "_");
+        assert_eq!(sanitize_file_name(".a"), ".a");
+    }
+
+    #[test]
+    fn partition_keys_differing_in_case() {
+        let dir = TestDir::new("partition-case");
+        let mut writer = PartitionWriter::new(
+            dir.join(""),
+            Some("country="),
+            Some(".csv"),
+            get_text_codec(&None).unwrap().1,
+            1,
+        );
+        writer.write_header("id\n").unwrap();
+        // files are closed and reopened, since only one file is kept open
+        for (key, record) in &[("JP", "1\n"), ("jp", "2\n"), ("US", "3\n"), ("Jp", "4\n")] {
+            writer.write_record(key, record).unwrap();
+        }
+        writer.finish().unwrap();
+        drop(writer);
+        assert_eq!(dir.file_names(), vec!["country=JP.csv", "country=US.csv"]);
+        assert_eq!(
+            std::fs::read_to_string(dir.join("country=JP.csv")).unwrap(),
+            "id\n1\n2\n4\n"
+        );
+    }
+
+    #[test]
+    fn expand_name_template_references() {
+        let re = Regex::new(r"(?P<year>\d{4})-(\d{2})").unwrap();
+        let caps = re.captures("2024-05");
+        assert_eq!(expand_name_template("$1-${2}", caps.as_ref()), "2024-05");
+        // a name continues while it is alphanumeric or "_", same as regex replacement
+        assert_eq!(expand_name_template("$1_${2}", caps.as_ref()), "05");
+        assert_eq!(
+            expand_name_template("y${year}.log", caps.as_ref()),
+            "y2024.log"
+        );
+        assert_eq!(expand_name_template("$year_x", caps.as_ref()), "");
+        // unknown group is removed
+        assert_eq!(expand_name_template("a${3}b${none}c", caps.as_ref()), "abc");
+        // references are removed without captures
+        assert_eq!(expand_name_template("a${1}b$2.c", None), "ab.c");
+    }
+
+    #[test]
+    fn expand_name_template_dollar() {
+        assert_eq!(expand_name_template("a$$1", None), "a$1");
+        assert_eq!(expand_name_template("$$$$", None), "$$");
+        assert_eq!(expand_name_template("a$", None), "a$");
+        assert_eq!(expand_name_template("a${1", None), "a${1");
+    }
+
+    #[test]
+    fn expand_name_template_sanitizes_captures() {
+        let re = Regex::new(r"name=(.*)").unwrap();
+        let caps = re.captures("name=a/b:c.");
+        assert_eq!(expand_name_template("x-$1", caps.as_ref()), "x-a_b_c_");
+    }
+}
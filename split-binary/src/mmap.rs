@@ -0,0 +1,57 @@
+use super::parallel::open_regular_file;
+use super::partfile::open_part;
+use super::{get_output_directory, BinaryOptions, Errors};
+use std::io::Write;
+
+/// maps input file into memory, returns None if input is stdin, a pipe or a special file.
+/// input must not be modified while it is mapped.
+pub(crate) fn map_input(filepath: &Option<String>) -> Result<Option<memmap2::Mmap>, Errors> {
+    let (f, _) = match open_regular_file(filepath)? {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    let map =
+        unsafe { memmap2::Mmap::map(&f) }.map_err(|e| Errors::from_io(&e, "mapping input file"))?;
+    #[cfg(unix)]
+    {
+        // only a hint, reading still works if it is not supported
+        let _ = map.advise(memmap2::Advice::Sequential);
+    }
+    Ok(Some(map))
+}
+
+/// splits mapped input, every part is written directly from the mapping
+pub(crate) fn split_binary_mmap(opts: &BinaryOptions, data: &[u8]) -> Result<(), Errors> {
+    let max_size = std::cmp::max(opts.max_size, 1);
+    let prefix = opts.prefix.clone().unwrap_or(String::from("x"));
+    let mut output_file_path = get_output_directory(&opts.output)?;
+    output_file_path.push(format!("{}.{}", prefix, ""));
+    let mut current_suffix = String::new();
+    let extra_suffix = opts.extra_suffix.clone().unwrap_or_default();
+    let mut offset = 0usize;
+    loop {
+        let mut output_file = open_part(
+            &mut current_suffix,
+            &prefix,
+            &mut output_file_path,
+            opts.is_numerical_suffix,
+            &extra_suffix,
+        )?;
+        if offset != 0 {
+            eprintln!("next suffix = {}", current_suffix);
+        }
+        let size = std::cmp::min(max_size, (data.len() - offset) as u64) as usize;
+        output_file
+            .write_all(&data[offset..offset + size])
+            .map_err(|e| Errors::from_io(&e, "writing output file"))?;
+        if opts.is_verbose {
+            eprintln!("{}: {} bytes by mmap", output_file_path.display(), size);
+        }
+        output_file.commit(opts.is_sync)?;
+        offset += size;
+        if offset >= data.len() {
+            break;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,221 @@
+use super::partfile::open_part;
+use super::sparse::data_ranges;
+use super::zerocopy::CopyMethod;
+use super::{get_next_suffix, get_output_directory, BinaryOptions, Errors, DEFAULT_BUFFER_SIZE};
+use std::io::{Seek, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+#[cfg(unix)]
+fn read_at(f: &std::fs::File, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    use std::os::unix::fs::FileExt;
+    f.read_at(buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(f: &std::fs::File, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    use std::os::windows::fs::FileExt;
+    f.seek_read(buf, offset)
+}
+
+/// returns input file and its length if input is a regular file
+pub(crate) fn open_regular_file(
+    filepath: &Option<String>,
+) -> Result<Option<(std::fs::File, u64)>, Errors> {
+    let filepath = match filepath {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    let f = std::fs::File::open(filepath).map_err(|e| Errors::from_io(&e, "opening input file"))?;
+    let metadata = f
+        .metadata()
+        .map_err(|e| Errors::from_io(&e, "reading input file metadata"))?;
+    if !metadata.is_file() {
+        return Ok(None);
+    }
+    Ok(Some((f, metadata.len())))
+}
+
+/// copies [offset, offset + length) of input to the part, holes of input are kept as holes.
+/// returns copied length, which is shorter than length if input is truncated.
+fn write_part(
+    input: &std::fs::File,
+    output: &mut std::fs::File,
+    offset: u64,
+    length: u64,
+    buf: &mut [u8],
+) -> Result<(u64, CopyMethod), Errors> {
+    let mut end = offset + length;
+    let mut method = CopyMethod::Hole;
+    for (data_start, data_end) in data_ranges(input, offset, end)? {
+        output
+            .seek(std::io::SeekFrom::Start(data_start - offset))
+            .map_err(|e| Errors::from_io(&e, "seeking output file"))?;
+        method = CopyMethod::Buffered;
+        let mut position = data_start;
+        while position < data_end {
+            let size = std::cmp::min(buf.len() as u64, data_end - position) as usize;
+            let bytesread = read_at(input, &mut buf[0..size], position)
+                .map_err(|e| Errors::from_io(&e, "reading from input file"))?;
+            if bytesread == 0 {
+                break;
+            }
+            output
+                .write_all(&buf[0..bytesread])
+                .map_err(|e| Errors::from_io(&e, "writing output file"))?;
+            position += bytesread as u64;
+        }
+        if position < data_end {
+            // input was truncated while splitting
+            end = position;
+            break;
+        }
+    }
+    // trailing hole is made by extending the part
+    output
+        .set_len(end - offset)
+        .map_err(|e| Errors::from_io(&e, "extending output file"))?;
+    Ok((end - offset, method))
+}
+
+/// splits a regular file with `jobs` threads, part ranges are computed from the file length
+/// and every thread reads its parts with positional reads.
+pub(crate) fn split_binary_parallel(
+    opts: &BinaryOptions,
+    input: std::fs::File,
+    length: u64,
+    jobs: usize,
+) -> Result<(), Errors> {
+    let max_size = std::cmp::max(opts.max_size, 1);
+    // an empty input still has the first part
+    let parts = std::cmp::max(length.div_ceil(max_size), 1) as usize;
+    let mut suffixes = Vec::with_capacity(parts);
+    let mut suffix = String::from(if opts.is_numerical_suffix { "0" } else { "aa" });
+    for _ in 0..parts {
+        let next_suffix = get_next_suffix(&suffix, opts.is_numerical_suffix);
+        suffixes.push(std::mem::replace(&mut suffix, next_suffix));
+    }
+    let prefix = opts.prefix.clone().unwrap_or(String::from("x"));
+    let mut output_file_path = get_output_directory(&opts.output)?;
+    output_file_path.push(format!("{}.{}", prefix, ""));
+    let extra_suffix = opts.extra_suffix.clone().unwrap_or_default();
+    let buffer_size = opts.buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE);
+    let next_part = AtomicUsize::new(0);
+    let is_failed = AtomicBool::new(false);
+    let results: Vec<Result<(), Errors>> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..std::cmp::min(jobs, parts))
+            .map(|_| {
+                scope.spawn(|| -> Result<(), Errors> {
+                    let mut buf = vec![0u8; buffer_size];
+                    while !is_failed.load(Ordering::Relaxed) {
+                        let i = next_part.fetch_add(1, Ordering::Relaxed);
+                        if i >= parts {
+                            break;
+                        }
+                        let offset = i as u64 * max_size;
+                        let result = open_part(
+                            &mut suffixes[i].clone(),
+                            &prefix,
+                            &mut output_file_path.clone(),
+                            opts.is_numerical_suffix,
+                            &extra_suffix,
+                        )
+                        .and_then(|mut output| {
+                            let (copied, method) = write_part(
+                                &input,
+                                &mut output,
+                                offset,
+                                std::cmp::min(max_size, length - offset.min(length)),
+                                &mut buf,
+                            )?;
+                            if opts.is_verbose {
+                                eprintln!(
+                                    "{}: {} bytes by {}",
+                                    output.path().display(),
+                                    copied,
+                                    method
+                                );
+                            }
+                            output.commit(opts.is_sync)
+                        });
+                        if result.is_err() {
+                            is_failed.store(true, Ordering::Relaxed);
+                            return result;
+                        }
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+            .collect()
+    });
+    results.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zerocopy::split_binary_file;
+    use crate::TestDir;
+
+    /// input with data, a hole in the middle and a trailing hole
+    fn create_input(path: &std::path::Path, length: u64) -> std::fs::File {
+        let mut f = std::fs::File::create(path).unwrap();
+        let data: Vec<u8> = (0..length.min(2500)).map(|v| (v % 251) as u8 + 1).collect();
+        f.write_all(&data).unwrap();
+        if length > 7000 {
+            f.seek(std::io::SeekFrom::Start(6000)).unwrap();
+            f.write_all(&data[..1000]).unwrap();
+        }
+        f.set_len(length).unwrap();
+        std::fs::File::open(path).unwrap()
+    }
+
+    fn read_parts(dir: &std::path::Path) -> Vec<(String, Vec<u8>)> {
+        let mut parts: Vec<(String, Vec<u8>)> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|v| {
+                let v = v.unwrap();
+                (
+                    v.file_name().to_string_lossy().into_owned(),
+                    std::fs::read(v.path()).unwrap(),
+                )
+            })
+            .collect();
+        parts.sort();
+        parts
+    }
+
+    #[test]
+    fn parallel_parts_match_sequential_split() {
+        let dir = TestDir::new("parallel");
+        for &length in &[0u64, 3000, 10500] {
+            let input_path = dir.join(&format!("in{}", length));
+            let sequential = dir.join(&format!("sequential{}", length));
+            let parallel = dir.join(&format!("parallel{}", length));
+            std::fs::create_dir(&sequential).unwrap();
+            std::fs::create_dir(&parallel).unwrap();
+            let opts = BinaryOptions::new(1000).with_buffer_size(Some(300));
+            split_binary_file(
+                &opts.with_output(sequential.to_str()),
+                create_input(&input_path, length),
+                length,
+                None,
+            )
+            .unwrap();
+            let opts = BinaryOptions::new(1000).with_buffer_size(Some(300));
+            split_binary_parallel(
+                &opts.with_output(parallel.to_str()),
+                std::fs::File::open(&input_path).unwrap(),
+                length,
+                3,
+            )
+            .unwrap();
+            let parts = read_parts(&sequential);
+            assert_eq!(parts.len() as u64, std::cmp::max(length.div_ceil(1000), 1));
+            assert_eq!(read_parts(&parallel), parts);
+        }
+    }
+}
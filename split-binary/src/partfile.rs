@@ -0,0 +1,179 @@
+use super::{next_part_path, Errors};
+
+/// part written to a temporary file in the output directory,
+/// renamed to its name only after it is completely written,
+/// so a file with the part name is always complete.
+/// temporary file is removed if the part is dropped without commit.
+pub(crate) struct PartFile {
+    file: std::fs::File,
+    temp_path: Option<std::path::PathBuf>,
+    path: std::path::PathBuf,
+}
+
+impl PartFile {
+    pub fn create(path: &std::path::Path) -> Result<Self, Errors> {
+        let mut temp_path = path.to_path_buf();
+        temp_path.set_file_name(format!(
+            ".{}.tmp-{}",
+            path.file_name()
+                .map(|v| v.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            std::process::id()
+        ));
+        let file = std::fs::File::create(&temp_path)
+            .map_err(|e| Errors::from_io(&e, "creating temporary part"))?;
+        Ok(PartFile {
+            file,
+            temp_path: Some(temp_path),
+            path: path.to_path_buf(),
+        })
+    }
+    /// part already opened under its name, it is kept even if it is dropped without commit
+    pub fn from_file(file: std::fs::File, path: &std::path::Path) -> Self {
+        PartFile {
+            file,
+            temp_path: None,
+            path: path.to_path_buf(),
+        }
+    }
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+    /// renames the part to its name, file and directory are synced to disk if is_sync
+    pub fn commit(mut self, is_sync: bool) -> Result<(), Errors> {
+        if is_sync {
+            self.file
+                .sync_all()
+                .map_err(|e| Errors::from_io(&e, "syncing part"))?;
+        }
+        if let Some(temp_path) = self.temp_path.take() {
+            std::fs::rename(&temp_path, &self.path)
+                .map_err(|e| Errors::from_io(&e, "renaming temporary part"))?;
+        }
+        if is_sync {
+            if let Some(parent) = self.path.parent() {
+                let parent = if parent.as_os_str().is_empty() {
+                    std::path::Path::new(".")
+                } else {
+                    parent
+                };
+                sync_directory(parent)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// syncs directory entries, so renamed files are kept after a crash
+pub(crate) fn sync_directory(directory: &std::path::Path) -> Result<(), Errors> {
+    #[cfg(unix)]
+    {
+        std::fs::File::open(directory)
+            .and_then(|v| v.sync_all())
+            .map_err(|e| Errors::from_io(&e, "syncing output directory"))?;
+    }
+    #[cfg(not(unix))]
+    let _ = directory;
+    Ok(())
+}
+
+impl Drop for PartFile {
+    fn drop(&mut self) {
+        if let Some(temp_path) = self.temp_path.take() {
+            let _ = std::fs::remove_file(temp_path);
+        }
+    }
+}
+
+impl std::ops::Deref for PartFile {
+    type Target = std::fs::File;
+    fn deref(&self) -> &std::fs::File {
+        &self.file
+    }
+}
+
+impl std::ops::DerefMut for PartFile {
+    fn deref_mut(&mut self) -> &mut std::fs::File {
+        &mut self.file
+    }
+}
+
+impl std::io::Write for PartFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.file.write(buf)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// same as open_file, but the part is created by PartFile
+pub(crate) fn open_part(
+    suffixstr: &mut String,
+    prefix: &str,
+    output_file_path: &mut std::path::PathBuf,
+    is_numerical_suffix: bool,
+    extra_suffix: &str,
+) -> Result<PartFile, Errors> {
+    next_part_path(
+        suffixstr,
+        prefix,
+        output_file_path,
+        is_numerical_suffix,
+        extra_suffix,
+    );
+    PartFile::create(output_file_path)
+}
+
+/// flushes buffered part and commits it
+pub(crate) fn commit_buffered(
+    output_file: std::io::BufWriter<PartFile>,
+    is_sync: bool,
+) -> Result<(), Errors> {
+    output_file
+        .into_inner()
+        .map_err(|e| Errors::from_io(e.error(), "writing output file"))?
+        .commit(is_sync)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestDir;
+    use std::io::Write;
+
+    #[test]
+    fn drop_removes_temporary_file() {
+        let dir = TestDir::new("partfile-drop");
+        let mut part = PartFile::create(&dir.join("xaa")).unwrap();
+        part.write_all(b"abc").unwrap();
+        assert_eq!(dir.file_names().len(), 1);
+        drop(part);
+        assert!(dir.file_names().is_empty());
+    }
+
+    #[test]
+    fn commit_renames_temporary_file() {
+        let dir = TestDir::new("partfile-commit");
+        let mut part = PartFile::create(&dir.join("xaa")).unwrap();
+        let temp_name = format!(".xaa.tmp-{}", std::process::id());
+        assert_eq!(dir.file_names(), vec![temp_name]);
+        part.write_all(b"abc").unwrap();
+        part.commit(true).unwrap();
+        assert_eq!(dir.file_names(), vec!["xaa"]);
+        assert_eq!(std::fs::read(dir.join("xaa")).unwrap(), b"abc");
+    }
+
+    #[test]
+    fn commit_buffered_part() {
+        let dir = TestDir::new("partfile-buffered");
+        let mut suffix = String::new();
+        let mut path = dir.join("x.");
+        let part = open_part(&mut suffix, "x", &mut path, false, ".bin").unwrap();
+        let mut output = std::io::BufWriter::new(part);
+        output.write_all(b"abc").unwrap();
+        commit_buffered(output, false).unwrap();
+        assert_eq!(dir.file_names(), vec!["xaa.bin"]);
+        assert_eq!(suffix, "ab");
+    }
+}
@@ -0,0 +1,424 @@
+use super::cdc::{sha256_file, to_hex};
+use super::partfile::PartFile;
+use super::{get_output_directory, next_part_path, BinaryOptions, Errors};
+use sha2::{Digest, Sha256};
+use std::io::{BufRead, Write};
+
+const JOURNAL_HEADER: &str = "# bsp split journal";
+
+fn invalid_data(message: String) -> Errors {
+    Errors::Io(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        message,
+    ))
+}
+
+/// first line of the journal, which has max-size and input,
+/// so a journal is not used to continue splitting another input.
+/// input length is unknown for stdin and other inputs than regular file.
+fn journal_header(opts: &BinaryOptions, input_length: Option<u64>) -> String {
+    let input = match (&opts.input, input_length) {
+        (Some(v), Some(length)) => format!(
+            "{:?} {} bytes",
+            std::fs::canonicalize(v).unwrap_or_else(|_| std::path::PathBuf::from(v)),
+            length
+        ),
+        (Some(v), None) => format!("{:?}", v),
+        (None, _) => String::from("stdin"),
+    };
+    format!(
+        "{}, max-size {}, input {}",
+        JOURNAL_HEADER, opts.max_size, input
+    )
+}
+
+/// records completed parts, so an interrupted split can be continued
+/// from the first part which is missing or does not match the journal.
+/// journal has a line per part: "file name\tlength\tsha256".
+pub(crate) struct Journal {
+    file: std::fs::File,
+    path: std::path::PathBuf,
+    is_sync: bool,
+    /// hash of the current part, data is added while it is written
+    hasher: Sha256,
+    /// input offset where splitting continues
+    pub offset: u64,
+    /// suffix of the next part
+    pub suffix: String,
+    /// whether the last part is already completed
+    pub is_done: bool,
+}
+
+/// returns length of the part if it has the name, length and hash in the journal line
+fn validate_part(line: &str, path: &std::path::Path, max_size: u64) -> Result<Option<u64>, Errors> {
+    let mut columns = line.split('\t');
+    let (name, length, hash) = match (columns.next(), columns.next(), columns.next()) {
+        (Some(name), Some(length), Some(hash)) => (name, length, hash),
+        _ => return Ok(None),
+    };
+    let length = match length.parse::<u64>() {
+        Ok(v) if v <= max_size => v,
+        _ => return Ok(None),
+    };
+    if path.file_name().map(|v| v.to_string_lossy()) != Some(name.into()) {
+        return Ok(None);
+    }
+    match std::fs::metadata(path) {
+        Ok(v) if v.is_file() && v.len() == length => (),
+        _ => return Ok(None),
+    }
+    if sha256_file(path)? != hash {
+        return Ok(None);
+    }
+    Ok(Some(length))
+}
+
+/// removes temporary files of the part which an interrupted split was writing,
+/// temporary files of other parts or other splits are kept.
+fn remove_temporary_part(part_path: &std::path::Path) -> Result<(), Errors> {
+    let directory = match part_path.parent() {
+        Some(v) if !v.as_os_str().is_empty() => v,
+        _ => std::path::Path::new("."),
+    };
+    // same name as PartFile::create, with pid of the interrupted split
+    let temp_prefix = format!(
+        ".{}.tmp-",
+        part_path
+            .file_name()
+            .map(|v| v.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    );
+    for entry in
+        std::fs::read_dir(directory).map_err(|e| Errors::from_io(&e, "reading output folder"))?
+    {
+        let entry = entry.map_err(|e| Errors::from_io(&e, "reading output folder"))?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let is_temporary_part = match name.strip_prefix(&temp_prefix) {
+            Some(pid) => !pid.is_empty() && pid.bytes().all(|b| b.is_ascii_digit()),
+            None => false,
+        };
+        if is_temporary_part {
+            std::fs::remove_file(entry.path())
+                .map_err(|e| Errors::from_io(&e, "removing temporary part"))?;
+        }
+    }
+    Ok(())
+}
+
+impl Journal {
+    /// reads journal of the previous split and validates its parts,
+    /// journal is created if it does not exist.
+    /// input_length is None if input is not a regular file.
+    pub fn open(opts: &BinaryOptions, input_length: Option<u64>) -> Result<Self, Errors> {
+        let prefix = opts.prefix.clone().unwrap_or(String::from("x"));
+        let extra_suffix = opts.extra_suffix.clone().unwrap_or_default();
+        let mut part_path = get_output_directory(&opts.output)?;
+        part_path.push(format!("{}.{}", prefix, ""));
+        let mut path = part_path.clone();
+        path.set_file_name(format!(".{}.bsp-journal", prefix));
+        let header = journal_header(opts, input_length);
+        let mut valid_lines = Vec::new();
+        let mut offset = 0u64;
+        let mut suffix = String::new();
+        let mut is_done = false;
+        match std::fs::File::open(&path) {
+            Ok(f) => {
+                let mut lines = std::io::BufReader::new(f).lines();
+                match lines.next() {
+                    Some(Ok(v)) if v == header => (),
+                    Some(Ok(v)) => {
+                        return Err(invalid_data(format!(
+                            "journal {:?} was written by another split({}), but this split is {}",
+                            path,
+                            v.trim_start_matches(JOURNAL_HEADER)
+                                .trim_start_matches(", "),
+                            header
+                                .trim_start_matches(JOURNAL_HEADER)
+                                .trim_start_matches(", ")
+                        )))
+                    }
+                    _ => {
+                        return Err(invalid_data(format!(
+                            "journal {:?} was not written by bsp",
+                            path
+                        )))
+                    }
+                }
+                let mut is_valid = true;
+                let mut line_count = 0usize;
+                for line in lines {
+                    let line = line.map_err(|e| Errors::from_io(&e, "reading journal"))?;
+                    // lines after an invalid part are counted to find the last part
+                    line_count += 1;
+                    if is_done || !is_valid {
+                        continue;
+                    }
+                    let mut next_suffix = suffix.clone();
+                    next_part_path(
+                        &mut next_suffix,
+                        &prefix,
+                        &mut part_path,
+                        opts.is_numerical_suffix,
+                        &extra_suffix,
+                    );
+                    let length = match validate_part(&line, &part_path, opts.max_size)? {
+                        Some(v) => v,
+                        None => {
+                            eprintln!(
+                                "{} does not match the journal, splitting again from it",
+                                part_path.display()
+                            );
+                            is_valid = false;
+                            continue;
+                        }
+                    };
+                    valid_lines.push(line);
+                    suffix = next_suffix;
+                    offset += length;
+                    // only the last part is shorter than max-size
+                    is_done = length < opts.max_size;
+                }
+                eprintln!(
+                    "{} parts are already completed, resuming at offset {}",
+                    valid_lines.len(),
+                    offset
+                );
+                // the interrupted split was writing the part after the last one in the journal
+                let mut temp_suffix = String::new();
+                let mut temp_part_path = part_path.clone();
+                for _ in 0..=line_count {
+                    next_part_path(
+                        &mut temp_suffix,
+                        &prefix,
+                        &mut temp_part_path,
+                        opts.is_numerical_suffix,
+                        &extra_suffix,
+                    );
+                }
+                remove_temporary_part(&temp_part_path)?;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => return Err(Errors::from_io(&e, "opening journal")),
+        }
+        // journal is rewritten without lines of invalid parts
+        let mut file =
+            std::fs::File::create(&path).map_err(|e| Errors::from_io(&e, "creating journal"))?;
+        let mut content = format!("{}\n", header);
+        for line in valid_lines.iter() {
+            content.push_str(line);
+            content.push('\n');
+        }
+        file.write_all(content.as_bytes())
+            .map_err(|e| Errors::from_io(&e, "writing journal"))?;
+        Ok(Journal {
+            file,
+            path,
+            is_sync: opts.is_sync,
+            hasher: Sha256::new(),
+            offset,
+            suffix,
+            is_done,
+        })
+    }
+    /// hasher of the current part
+    pub fn hasher(&mut self) -> &mut Sha256 {
+        &mut self.hasher
+    }
+    /// adds data written to the current part to its hash
+    pub fn update(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+    }
+    /// appends a committed part with hash of the data added since the previous part
+    pub fn record(&mut self, path: &std::path::Path, length: u64) -> Result<(), Errors> {
+        let line = format!(
+            "{}\t{}\t{}\n",
+            path.file_name()
+                .map(|v| v.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            length,
+            to_hex(&self.hasher.finalize_reset())
+        );
+        self.file
+            .write_all(line.as_bytes())
+            .map_err(|e| Errors::from_io(&e, "writing journal"))?;
+        if self.is_sync {
+            self.file
+                .sync_data()
+                .map_err(|e| Errors::from_io(&e, "syncing journal"))?;
+        }
+        Ok(())
+    }
+    /// journal is removed after all parts are written
+    pub fn finish(self) -> Result<(), Errors> {
+        drop(self.file);
+        std::fs::remove_file(&self.path).map_err(|e| Errors::from_io(&e, "removing journal"))
+    }
+}
+
+/// commits the part and records it in the journal if resuming is enabled
+pub(crate) fn commit_part(
+    part: PartFile,
+    length: u64,
+    is_sync: bool,
+    journal: Option<&mut Journal>,
+) -> Result<(), Errors> {
+    let path = part.path().to_path_buf();
+    part.commit(is_sync)?;
+    if let Some(journal) = journal {
+        journal.record(&path, length)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn input_shorter_error(offset: u64, length: u64) -> Errors {
+    invalid_data(format!(
+        "input has only {} bytes, but parts of {} bytes are in the journal",
+        length, offset
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zerocopy::split_binary_file;
+    use crate::TestDir;
+
+    const JOURNAL_NAME: &str = ".x.bsp-journal";
+
+    fn test_data(length: usize) -> Vec<u8> {
+        (0..length).map(|v| (v % 251) as u8).collect()
+    }
+
+    /// options to split "in" of the directory into "out"
+    fn options(dir: &TestDir, max_size: u64) -> BinaryOptions {
+        BinaryOptions::new(max_size)
+            .with_input(dir.join("in").to_str())
+            .with_output(dir.join("out").to_str())
+            .with_is_resume(true)
+    }
+
+    fn prepare(dir: &TestDir, data: &[u8], max_size: u64) -> BinaryOptions {
+        std::fs::write(dir.join("in"), data).unwrap();
+        std::fs::create_dir_all(dir.join("out")).unwrap();
+        options(dir, max_size)
+    }
+
+    /// splits input with journal, the journal is kept like an interrupted split
+    fn split(opts: &BinaryOptions) -> Journal {
+        let input = std::fs::File::open(opts.input.as_ref().unwrap()).unwrap();
+        let length = input.metadata().unwrap().len();
+        let mut journal = Journal::open(opts, Some(length)).unwrap();
+        split_binary_file(opts, input, length, Some(&mut journal)).unwrap();
+        journal
+    }
+
+    fn out_names(dir: &TestDir) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir.join("out"))
+            .unwrap()
+            .map(|v| v.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn journal_of_another_split() {
+        let dir = TestDir::new("resume-header");
+        let opts = prepare(&dir, &test_data(1000), 300);
+        drop(split(&opts));
+        // another max-size
+        assert!(Journal::open(&options(&dir, 200), Some(1000)).is_err());
+        // another input length
+        assert!(Journal::open(&opts, Some(900)).is_err());
+        // stdin
+        assert!(Journal::open(&options(&dir, 300).with_input(None), None).is_err());
+        std::fs::write(dir.join("out").join(JOURNAL_NAME), "abc\n").unwrap();
+        assert!(Journal::open(&opts, Some(1000)).is_err());
+    }
+
+    #[test]
+    fn validate_part_lines() {
+        let dir = TestDir::new("resume-validate");
+        let path = dir.join("xaa");
+        std::fs::write(&path, b"abc").unwrap();
+        let line = format!("xaa\t3\t{}", sha256_file(&path).unwrap());
+        assert_eq!(validate_part(&line, &path, 3).unwrap(), Some(3));
+        // length is larger than max-size
+        assert_eq!(validate_part(&line, &path, 2).unwrap(), None);
+        // another part name
+        assert_eq!(validate_part(&line, &dir.join("xab"), 3).unwrap(), None);
+        assert_eq!(validate_part("xaa\t3", &path, 3).unwrap(), None);
+        // tampered part
+        std::fs::write(&path, b"abd").unwrap();
+        assert_eq!(validate_part(&line, &path, 3).unwrap(), None);
+        // short part
+        std::fs::write(&path, b"ab").unwrap();
+        assert_eq!(validate_part(&line, &path, 3).unwrap(), None);
+    }
+
+    #[test]
+    fn resume_after_last_part() {
+        let dir = TestDir::new("resume-exact");
+        // input length is a multiple of max-size, so the last part is not shorter
+        let data = test_data(900);
+        let opts = prepare(&dir, &data, 300);
+        drop(split(&opts));
+        assert_eq!(out_names(&dir), vec![JOURNAL_NAME, "xaa", "xab", "xac"]);
+        let mut journal = Journal::open(&opts, Some(900)).unwrap();
+        assert_eq!(journal.offset, 900);
+        assert_eq!(journal.suffix, "ad");
+        assert!(!journal.is_done);
+        let input = std::fs::File::open(dir.join("in")).unwrap();
+        split_binary_file(&opts, input, 900, Some(&mut journal)).unwrap();
+        journal.finish().unwrap();
+        // no empty part is added
+        assert_eq!(out_names(&dir), vec!["xaa", "xab", "xac"]);
+    }
+
+    #[test]
+    fn resume_from_tampered_part() {
+        let dir = TestDir::new("resume-tampered");
+        let data = test_data(1000);
+        let opts = prepare(&dir, &data, 300);
+        drop(split(&opts));
+        std::fs::write(dir.join("out").join("xab"), vec![0u8; 300]).unwrap();
+        let journal = Journal::open(&opts, Some(1000)).unwrap();
+        assert_eq!(journal.offset, 300);
+        assert_eq!(journal.suffix, "ab");
+        drop(journal);
+        split(&opts).finish().unwrap();
+        assert_eq!(out_names(&dir), vec!["xaa", "xab", "xac", "xad"]);
+        let parts: Vec<u8> = ["xaa", "xab", "xac", "xad"]
+            .iter()
+            .flat_map(|v| std::fs::read(dir.join("out").join(v)).unwrap())
+            .collect();
+        assert_eq!(parts, data);
+    }
+
+    #[test]
+    fn remove_only_interrupted_temporary_part() {
+        let dir = TestDir::new("resume-temporary");
+        let opts = prepare(&dir, &test_data(1000), 300);
+        let journal = split(&opts);
+        drop(journal);
+        // journal has 4 parts, so the interrupted split was writing xae
+        for name in [".xae.tmp-123", ".xae.tmp-12a", ".xyz.tmp-1", ".xad.tmp-5"] {
+            std::fs::write(dir.join("out").join(name), b"").unwrap();
+        }
+        let journal = Journal::open(&opts, Some(1000)).unwrap();
+        assert!(journal.is_done);
+        assert_eq!(
+            out_names(&dir),
+            vec![
+                ".x.bsp-journal",
+                ".xad.tmp-5",
+                ".xae.tmp-12a",
+                ".xyz.tmp-1",
+                "xaa",
+                "xab",
+                "xac",
+                "xad"
+            ]
+        );
+    }
+}
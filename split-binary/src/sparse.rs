@@ -0,0 +1,336 @@
+use super::zerocopy::{copy_between_files, CopyMethod};
+use super::Errors;
+use sha2::{Digest, Sha256};
+use std::io::{Read, Seek, Write};
+
+/// zeros are detected in blocks of this size, which is a usual file system block size
+const ZERO_BLOCK_SIZE: usize = 4096;
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::os::unix::io::AsRawFd;
+
+    /// returns None if there is no data(or hole) after offset
+    fn seek(f: &std::fs::File, offset: u64, whence: libc::c_int) -> std::io::Result<Option<u64>> {
+        let ret = unsafe { libc::lseek(f.as_raw_fd(), offset as libc::off_t, whence) };
+        if ret < 0 {
+            let e = std::io::Error::last_os_error();
+            if e.raw_os_error() == Some(libc::ENXIO) {
+                return Ok(None);
+            }
+            return Err(e);
+        }
+        Ok(Some(ret as u64))
+    }
+
+    /// returns None if the file system can not report holes
+    pub fn data_ranges(
+        f: &std::fs::File,
+        start: u64,
+        end: u64,
+    ) -> std::io::Result<Option<Vec<(u64, u64)>>> {
+        let mut ranges = Vec::new();
+        let mut position = start;
+        while position < end {
+            let data = match seek(f, position, libc::SEEK_DATA) {
+                Ok(Some(v)) => v,
+                Ok(None) => break,
+                Err(e)
+                    if matches!(
+                        e.raw_os_error(),
+                        Some(libc::EINVAL) | Some(libc::EOPNOTSUPP)
+                    ) =>
+                {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e),
+            };
+            if data >= end {
+                break;
+            }
+            let hole = seek(f, data, libc::SEEK_HOLE)?.unwrap_or(end);
+            let hole = std::cmp::min(hole, end);
+            ranges.push((data, hole));
+            position = hole;
+        }
+        Ok(Some(ranges))
+    }
+}
+
+/// data regions in [start, end) of the file, holes between them are read as zeros.
+/// whole range is data if holes can not be detected.
+pub(crate) fn data_ranges(
+    f: &std::fs::File,
+    start: u64,
+    end: u64,
+) -> Result<Vec<(u64, u64)>, Errors> {
+    #[cfg(target_os = "linux")]
+    {
+        if let Some(v) = linux::data_ranges(f, start, end)
+            .map_err(|e| Errors::from_io(&e, "finding holes of input file"))?
+        {
+            return Ok(v);
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = f;
+    Ok(if start < end {
+        vec![(start, end)]
+    } else {
+        Vec::new()
+    })
+}
+
+fn is_zero(b: &[u8]) -> bool {
+    b.iter().all(|&v| v == 0)
+}
+
+/// copies up to length bytes through buf, blocks of only zeros are seeked over in output.
+/// copied data is also added to hasher if it is given.
+pub(crate) fn copy_skipping_zeros(
+    input: &mut std::fs::File,
+    output: &mut std::fs::File,
+    length: u64,
+    buf: &mut [u8],
+    mut hasher: Option<&mut Sha256>,
+) -> Result<u64, Errors> {
+    let mut copied = 0u64;
+    while copied < length {
+        let size = std::cmp::min(buf.len() as u64, length - copied) as usize;
+        let bytesread = input
+            .read(&mut buf[0..size])
+            .map_err(|e| Errors::from_io(&e, "reading from input file"))?;
+        if bytesread == 0 {
+            break;
+        }
+        if let Some(hasher) = hasher.as_deref_mut() {
+            hasher.update(&buf[0..bytesread]);
+        }
+        let mut position = 0usize;
+        while position < bytesread {
+            // consecutive blocks of the same kind are written or seeked at once
+            let is_hole =
+                is_zero(&buf[position..std::cmp::min(position + ZERO_BLOCK_SIZE, bytesread)]);
+            let mut run_end = std::cmp::min(position + ZERO_BLOCK_SIZE, bytesread);
+            while run_end < bytesread {
+                let next = std::cmp::min(run_end + ZERO_BLOCK_SIZE, bytesread);
+                if is_zero(&buf[run_end..next]) != is_hole {
+                    break;
+                }
+                run_end = next;
+            }
+            if is_hole {
+                output
+                    .seek(std::io::SeekFrom::Current((run_end - position) as i64))
+                    .map_err(|e| Errors::from_io(&e, "seeking output file"))?;
+            } else {
+                output
+                    .write_all(&buf[position..run_end])
+                    .map_err(|e| Errors::from_io(&e, "writing output file"))?;
+            }
+            position = run_end;
+        }
+        copied += bytesread as u64;
+    }
+    Ok(copied)
+}
+
+/// copies [start, end) of input to the current position of output,
+/// holes of input are seeked over so they stay holes in output.
+/// if is_zero_skipped, blocks of only zeros are also seeked over when data is copied through buf.
+/// returns copied length including holes, which is shorter than the range if input is truncated.
+pub(crate) fn copy_sparse(
+    input: &mut std::fs::File,
+    output: &mut std::fs::File,
+    start: u64,
+    end: u64,
+    buf: &mut [u8],
+    is_zero_skipped: bool,
+) -> Result<(u64, CopyMethod), Errors> {
+    let output_start = output
+        .stream_position()
+        .map_err(|e| Errors::from_io(&e, "seeking output file"))?;
+    // range after the end of input is not a hole
+    let input_length = input
+        .metadata()
+        .map_err(|e| Errors::from_io(&e, "reading input file metadata"))?
+        .len();
+    let end = std::cmp::min(end, input_length);
+    let mut length = end.saturating_sub(start);
+    let mut method = CopyMethod::Hole;
+    for (data_start, data_end) in data_ranges(input, start, end)? {
+        input
+            .seek(std::io::SeekFrom::Start(data_start))
+            .map_err(|e| Errors::from_io(&e, "seeking input file"))?;
+        output
+            .seek(std::io::SeekFrom::Start(output_start + data_start - start))
+            .map_err(|e| Errors::from_io(&e, "seeking output file"))?;
+        let (copied, m) =
+            copy_between_files(input, output, data_end - data_start, buf, is_zero_skipped)?;
+        method = m;
+        if copied < data_end - data_start {
+            length = data_start + copied - start;
+            break;
+        }
+    }
+    extend_output(output, output_start + length)?;
+    Ok((length, method))
+}
+
+/// copies [start, end) of input through buf and adds it to hasher,
+/// blocks of only zeros are seeked over so holes of input stay holes in output.
+/// returns copied length, which is shorter than the range if input is truncated.
+pub(crate) fn copy_hashed(
+    input: &mut std::fs::File,
+    output: &mut std::fs::File,
+    start: u64,
+    end: u64,
+    buf: &mut [u8],
+    hasher: &mut Sha256,
+) -> Result<u64, Errors> {
+    let output_start = output
+        .stream_position()
+        .map_err(|e| Errors::from_io(&e, "seeking output file"))?;
+    input
+        .seek(std::io::SeekFrom::Start(start))
+        .map_err(|e| Errors::from_io(&e, "seeking input file"))?;
+    let length = copy_skipping_zeros(input, output, end.saturating_sub(start), buf, Some(hasher))?;
+    extend_output(output, output_start + length)?;
+    Ok(length)
+}
+
+/// trailing hole is made by extending the file, output is positioned at output_end
+fn extend_output(output: &mut std::fs::File, output_end: u64) -> Result<(), Errors> {
+    let output_length = output
+        .metadata()
+        .map_err(|e| Errors::from_io(&e, "reading output file metadata"))?
+        .len();
+    if output_length < output_end {
+        output
+            .set_len(output_end)
+            .map_err(|e| Errors::from_io(&e, "extending output file"))?;
+    }
+    output
+        .seek(std::io::SeekFrom::Start(output_end))
+        .map_err(|e| Errors::from_io(&e, "seeking output file"))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestDir;
+
+    fn create_file(path: &std::path::Path, data: &[u8]) -> std::fs::File {
+        std::fs::write(path, data).unwrap();
+        std::fs::File::open(path).unwrap()
+    }
+
+    fn create_output(path: &std::path::Path, prefix: &[u8]) -> std::fs::File {
+        let mut f = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .read(true)
+            .write(true)
+            .open(path)
+            .unwrap();
+        f.write_all(prefix).unwrap();
+        f
+    }
+
+    fn test_data(length: usize) -> Vec<u8> {
+        (0..length).map(|v| (v % 251) as u8 + 1).collect()
+    }
+
+    #[test]
+    fn copy_sparse_range() {
+        let dir = TestDir::new("sparse-range");
+        let data = test_data(10000);
+        let mut input = create_file(&dir.join("in"), &data);
+        let mut output = create_output(&dir.join("out"), b"head");
+        let mut buf = vec![0u8; 64];
+        let (copied, _) = copy_sparse(&mut input, &mut output, 100, 5100, &mut buf, false).unwrap();
+        assert_eq!(copied, 5000);
+        // output is positioned after the copied range
+        assert_eq!(output.stream_position().unwrap(), 5004);
+        let written = std::fs::read(dir.join("out")).unwrap();
+        assert_eq!(&written[0..4], b"head");
+        assert_eq!(&written[4..], &data[100..5100]);
+    }
+
+    #[test]
+    fn copy_sparse_truncated_input() {
+        let dir = TestDir::new("sparse-truncated");
+        let data = test_data(1000);
+        let mut input = create_file(&dir.join("in"), &data);
+        let mut output = create_output(&dir.join("out"), b"");
+        let mut buf = vec![0u8; 64];
+        let (copied, _) = copy_sparse(&mut input, &mut output, 900, 1300, &mut buf, false).unwrap();
+        assert_eq!(copied, 100);
+        assert_eq!(std::fs::read(dir.join("out")).unwrap(), &data[900..]);
+    }
+
+    #[test]
+    fn copy_sparse_keeps_holes() {
+        let dir = TestDir::new("sparse-holes");
+        let path = dir.join("in");
+        let mut f = create_output(&path, b"");
+        f.seek(std::io::SeekFrom::Start(1 << 20)).unwrap();
+        f.write_all(b"data").unwrap();
+        // trailing hole
+        f.set_len(3 << 20).unwrap();
+        drop(f);
+        let mut input = std::fs::File::open(&path).unwrap();
+        let mut output = create_output(&dir.join("out"), b"");
+        let mut buf = vec![0u8; 4096];
+        let (copied, _) = copy_sparse(&mut input, &mut output, 0, 3 << 20, &mut buf, true).unwrap();
+        assert_eq!(copied, 3 << 20);
+        assert_eq!(
+            std::fs::read(dir.join("out")).unwrap(),
+            std::fs::read(&path).unwrap()
+        );
+    }
+
+    #[test]
+    fn copy_hashed_range() {
+        let dir = TestDir::new("sparse-hashed");
+        let mut data = test_data(20000);
+        // zero blocks are seeked over in output, but hashed as data
+        for v in data[4096..12288].iter_mut() {
+            *v = 0;
+        }
+        let mut input = create_file(&dir.join("in"), &data);
+        let mut output = create_output(&dir.join("out"), b"");
+        let mut buf = vec![0u8; 10000];
+        let mut hasher = Sha256::new();
+        let copied =
+            copy_hashed(&mut input, &mut output, 1000, 19000, &mut buf, &mut hasher).unwrap();
+        assert_eq!(copied, 18000);
+        assert_eq!(output.stream_position().unwrap(), 18000);
+        assert_eq!(std::fs::read(dir.join("out")).unwrap(), &data[1000..19000]);
+        assert_eq!(hasher.finalize(), Sha256::digest(&data[1000..19000]));
+        // range beyond the end of input
+        let mut output = create_output(&dir.join("out2"), b"");
+        let mut hasher = Sha256::new();
+        let copied =
+            copy_hashed(&mut input, &mut output, 19000, 30000, &mut buf, &mut hasher).unwrap();
+        assert_eq!(copied, 1000);
+        assert_eq!(hasher.finalize(), Sha256::digest(&data[19000..]));
+    }
+
+    #[test]
+    fn copy_skipping_zeros_trailing_zeros() {
+        let dir = TestDir::new("sparse-zeros");
+        let mut data = vec![0u8; 3 * ZERO_BLOCK_SIZE];
+        data[ZERO_BLOCK_SIZE + 1] = 1;
+        let mut input = create_file(&dir.join("in"), &data);
+        let mut output = create_output(&dir.join("out"), b"");
+        let mut buf = vec![0u8; 5000];
+        let copied =
+            copy_skipping_zeros(&mut input, &mut output, u64::MAX, &mut buf, None).unwrap();
+        assert_eq!(copied, data.len() as u64);
+        // trailing zeros are only seeked over, so the file is extended by caller
+        extend_output(&mut output, copied).unwrap();
+        assert_eq!(std::fs::read(dir.join("out")).unwrap(), data);
+    }
+}
@@ -0,0 +1,395 @@
+use super::cdc::to_hex;
+use super::partfile::{commit_buffered, sync_directory, PartFile};
+use super::{ensure_dir, get_file_or_stdin, BinaryOptions, Errors, DEFAULT_BUFFER_SIZE};
+use sha2::{Digest, Sha256};
+use std::io::{BufRead, Read, Seek, Write};
+
+const INDEX_HEADER: &str = "# bsp chunk index";
+
+fn invalid_data(message: String) -> Errors {
+    Errors::Io(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        message,
+    ))
+}
+
+/// path of a chunk in the store, "ab/cdef..." for hash "abcdef..."
+fn chunk_path(directory: &std::path::Path, hash: &str) -> std::path::PathBuf {
+    let mut path = directory.to_path_buf();
+    path.push(&hash[0..2]);
+    path.push(&hash[2..]);
+    path
+}
+
+/// content addressed chunk store, chunks already in the store are not written again.
+/// index has a line per chunk: "sha256\tlength".
+/// index is written to a temporary file until finish, and temporary chunk is removed on drop.
+pub(crate) struct ChunkStore {
+    directory: std::path::PathBuf,
+    index: Option<std::io::BufWriter<PartFile>>,
+    temp: Option<(std::fs::File, std::path::PathBuf)>,
+    is_sync: bool,
+    hasher: Sha256,
+    length: u64,
+    chunks: u64,
+    stored_chunks: u64,
+}
+
+impl ChunkStore {
+    pub fn new(directory: &str, index: &str, is_sync: bool) -> Result<Self, Errors> {
+        let directory = std::path::PathBuf::from(directory);
+        ensure_dir(&directory)?;
+        let mut index = std::io::BufWriter::new(PartFile::create(std::path::Path::new(index))?);
+        writeln!(index, "{}", INDEX_HEADER).map_err(|e| Errors::from_io(&e, "writing index"))?;
+        Ok(ChunkStore {
+            directory,
+            index: Some(index),
+            temp: None,
+            is_sync,
+            hasher: Sha256::new(),
+            length: 0,
+            chunks: 0,
+            stored_chunks: 0,
+        })
+    }
+    /// data is written to a temporary file until the chunk is finished
+    pub fn write(&mut self, data: &[u8]) -> Result<(), Errors> {
+        if self.temp.is_none() {
+            let mut path = self.directory.clone();
+            path.push(format!(".tmp-{}-{}", std::process::id(), self.chunks));
+            let f = std::fs::File::create(&path)
+                .map_err(|e| Errors::from_io(&e, "creating temporary chunk"))?;
+            self.temp = Some((f, path));
+        }
+        if let Some((f, _)) = &mut self.temp {
+            f.write_all(data)
+                .map_err(|e| Errors::from_io(&e, "writing temporary chunk"))?;
+        }
+        self.hasher.update(data);
+        self.length += data.len() as u64;
+        Ok(())
+    }
+    /// moves the chunk to its hash path and appends it to index, empty chunk is ignored
+    pub fn finish_chunk(&mut self) -> Result<(), Errors> {
+        let (f, temp_path) = match self.temp.take() {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+        let hash = to_hex(&std::mem::take(&mut self.hasher).finalize());
+        let path = chunk_path(&self.directory, &hash);
+        if path.exists() {
+            drop(f);
+            std::fs::remove_file(&temp_path)
+                .map_err(|e| Errors::from_io(&e, "removing temporary chunk"))?;
+        } else {
+            // temporary chunk is removed by drop if storing fails
+            self.temp = Some((f, temp_path));
+            self.store_chunk(&path)?;
+            self.stored_chunks += 1;
+        }
+        if let Some(index) = &mut self.index {
+            writeln!(index, "{}\t{}", hash, self.length)
+                .map_err(|e| Errors::from_io(&e, "writing index"))?;
+        }
+        self.chunks += 1;
+        self.length = 0;
+        Ok(())
+    }
+    /// renames the temporary chunk to path, chunk and directories are synced to disk if is_sync
+    fn store_chunk(&mut self, path: &std::path::Path) -> Result<(), Errors> {
+        let parent = path.parent().unwrap_or(&self.directory);
+        let is_new_parent = !parent.exists();
+        ensure_dir(parent)?;
+        if let Some((f, temp_path)) = &self.temp {
+            if self.is_sync {
+                f.sync_all()
+                    .map_err(|e| Errors::from_io(&e, "syncing chunk"))?;
+            }
+            std::fs::rename(temp_path, path).map_err(|e| Errors::from_io(&e, "storing chunk"))?;
+        }
+        self.temp = None;
+        if self.is_sync {
+            sync_directory(parent)?;
+            if is_new_parent {
+                sync_directory(&self.directory)?;
+            }
+        }
+        Ok(())
+    }
+    /// finishes the last chunk and commits the index
+    pub fn finish(mut self) -> Result<(), Errors> {
+        self.finish_chunk()?;
+        if let Some(index) = self.index.take() {
+            commit_buffered(index, self.is_sync)?;
+        }
+        eprintln!(
+            "{} chunks, {} newly stored, {} already in store",
+            self.chunks,
+            self.stored_chunks,
+            self.chunks - self.stored_chunks
+        );
+        Ok(())
+    }
+}
+
+impl Drop for ChunkStore {
+    fn drop(&mut self) {
+        if let Some((_, temp_path)) = self.temp.take() {
+            let _ = std::fs::remove_file(temp_path);
+        }
+    }
+}
+
+/// fixed-size split into chunk store instead of output files, returns input length.
+/// index is written by ChunkStore::finish after it.
+pub(crate) fn split_binary_store(
+    opts: &BinaryOptions,
+    store: &mut ChunkStore,
+) -> Result<u64, Errors> {
+    let mut input = get_file_or_stdin(&opts.input)?;
+    let mut buf = vec![0; opts.buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE)];
+    let mut available = opts.max_size;
+    let mut totalbytes = 0u64;
+    loop {
+        let bytesread = input
+            .read(&mut buf)
+            .map_err(|e| Errors::from_io(&e, "reading from input file"))?;
+        if bytesread == 0 {
+            break;
+        }
+        totalbytes += bytesread as u64;
+        let mut offset = 0usize;
+        while offset < bytesread {
+            let bytesavailable = std::cmp::min((bytesread - offset) as u64, available) as usize;
+            store.write(&buf[offset..offset + bytesavailable])?;
+            available -= bytesavailable as u64;
+            offset += bytesavailable;
+            if available == 0 {
+                store.finish_chunk()?;
+                available = opts.max_size;
+            }
+        }
+    }
+    Ok(totalbytes)
+}
+
+/// opens the chunk and verifies its length and hash, chunk is positioned at the start
+fn open_chunk(
+    path: &std::path::Path,
+    length: u64,
+    hash: &str,
+    buf: &mut [u8],
+) -> Result<std::fs::File, Errors> {
+    let mut chunk = std::fs::File::open(path)
+        .map_err(|e| Errors::from_io(&e, &format!("opening chunk {:?}", path)))?;
+    let mut hasher = Sha256::new();
+    let mut chunk_length = 0u64;
+    loop {
+        let bytesread = chunk
+            .read(buf)
+            .map_err(|e| Errors::from_io(&e, &format!("reading chunk {:?}", path)))?;
+        if bytesread == 0 {
+            break;
+        }
+        hasher.update(&buf[0..bytesread]);
+        chunk_length += bytesread as u64;
+    }
+    if chunk_length != length || to_hex(&hasher.finalize()) != hash {
+        return Err(invalid_data(format!(
+            "chunk {:?} is corrupted(expected {} bytes, found {} bytes)",
+            path, length, chunk_length
+        )));
+    }
+    chunk
+        .rewind()
+        .map_err(|e| Errors::from_io(&e, &format!("seeking chunk {:?}", path)))?;
+    Ok(chunk)
+}
+
+/// writes chunks listed in index to output, every chunk is verified by its length and hash
+/// before it is written.
+pub(crate) fn combine_from_store<W: Write>(
+    index: &str,
+    directory: &str,
+    output: &mut W,
+    buf: &mut [u8],
+) -> Result<(), Errors> {
+    let directory = std::path::Path::new(directory);
+    let f = std::fs::File::open(index).map_err(|e| Errors::from_io(&e, "opening index"))?;
+    for (i, line) in std::io::BufReader::new(f).lines().enumerate() {
+        let line = line.map_err(|e| Errors::from_io(&e, "reading index"))?;
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut columns = line.split('\t');
+        let hash = columns.next().unwrap_or_default();
+        let length = columns.next().and_then(|v| v.parse::<u64>().ok());
+        let length = match length {
+            Some(v) if hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit()) => v,
+            _ => {
+                return Err(invalid_data(format!(
+                    "invalid index line {}: {}",
+                    i + 1,
+                    line
+                )))
+            }
+        };
+        let hash = hash.to_ascii_lowercase();
+        let path = chunk_path(directory, &hash);
+        let mut chunk = open_chunk(&path, length, &hash, buf)?;
+        let mut copied = 0u64;
+        while copied < length {
+            let size = std::cmp::min(buf.len() as u64, length - copied) as usize;
+            let bytesread = chunk
+                .read(&mut buf[0..size])
+                .map_err(|e| Errors::from_io(&e, &format!("reading chunk {:?}", path)))?;
+            if bytesread == 0 {
+                return Err(invalid_data(format!(
+                    "chunk {:?} is truncated while reading",
+                    path
+                )));
+            }
+            output
+                .write_all(&buf[0..bytesread])
+                .map_err(|e| Errors::from_io(&e, "failed to write to output"))?;
+            copied += bytesread as u64;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestDir;
+
+    /// 3000 bytes where the first two chunks of 1000 bytes are the same
+    fn test_data() -> Vec<u8> {
+        let mut data: Vec<u8> = (0..1000).map(|v| (v % 251) as u8).collect();
+        data.extend_from_within(..);
+        data.extend((0..1000).map(|v| (v % 13) as u8));
+        data
+    }
+
+    /// splits data into the store by max-size of 1000, returns paths of the index and the store
+    fn store(dir: &TestDir, data: &[u8]) -> (String, String) {
+        let input = dir.join("in");
+        std::fs::write(&input, data).unwrap();
+        let index = dir.join("index").to_string_lossy().into_owned();
+        let directory = dir.join("store").to_string_lossy().into_owned();
+        let opts = BinaryOptions::new(1000).with_input(input.to_str());
+        let mut store = ChunkStore::new(&directory, &index, false).unwrap();
+        assert_eq!(
+            split_binary_store(&opts, &mut store).unwrap(),
+            data.len() as u64
+        );
+        store.finish().unwrap();
+        (index, directory)
+    }
+
+    fn stored_files(directory: &str) -> Vec<String> {
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(directory).unwrap() {
+            let entry = entry.unwrap();
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if entry.path().is_dir() {
+                for chunk in std::fs::read_dir(entry.path()).unwrap() {
+                    files.push(format!(
+                        "{}{}",
+                        name,
+                        chunk.unwrap().file_name().to_string_lossy()
+                    ));
+                }
+            } else {
+                files.push(name);
+            }
+        }
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn store_round_trip() {
+        let dir = TestDir::new("store-round-trip");
+        let data = test_data();
+        let (index, directory) = store(&dir, &data);
+        let index_lines = std::fs::read_to_string(&index).unwrap();
+        let lines: Vec<&str> = index_lines.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], INDEX_HEADER);
+        assert_eq!(lines[1], lines[2]);
+        // same chunks are stored once, and no temporary file is left
+        let mut hashes: Vec<String> = lines[1..]
+            .iter()
+            .map(|v| v.split('\t').next().unwrap().to_owned())
+            .collect();
+        hashes.dedup();
+        assert_eq!(stored_files(&directory), {
+            let mut v = hashes.clone();
+            v.sort();
+            v
+        });
+        let mut output = Vec::new();
+        combine_from_store(&index, &directory, &mut output, &mut [0u8; 300]).unwrap();
+        assert_eq!(output, data);
+    }
+
+    #[test]
+    fn store_same_input_again() {
+        let dir = TestDir::new("store-again");
+        let data = test_data();
+        let (index, directory) = store(&dir, &data);
+        let first_index = std::fs::read_to_string(&index).unwrap();
+        let first_files = stored_files(&directory);
+        let (index, directory) = store(&dir, &data);
+        assert_eq!(std::fs::read_to_string(&index).unwrap(), first_index);
+        assert_eq!(stored_files(&directory), first_files);
+    }
+
+    #[test]
+    fn combine_corrupted_chunk() {
+        let dir = TestDir::new("store-corrupted");
+        let data = test_data();
+        let (index, directory) = store(&dir, &data);
+        let index_lines = std::fs::read_to_string(&index).unwrap();
+        let last_hash = index_lines
+            .lines()
+            .last()
+            .unwrap()
+            .split('\t')
+            .next()
+            .unwrap();
+        let path = chunk_path(std::path::Path::new(&directory), last_hash);
+        let mut chunk = std::fs::read(&path).unwrap();
+        chunk[500] ^= 1;
+        std::fs::write(&path, &chunk).unwrap();
+        let mut output = Vec::new();
+        assert!(combine_from_store(&index, &directory, &mut output, &mut [0u8; 300]).is_err());
+        // corrupted chunk is not written
+        assert_eq!(output, &data[..2000]);
+        // short chunk
+        std::fs::write(&path, &data[2000..2999]).unwrap();
+        let mut output = Vec::new();
+        assert!(combine_from_store(&index, &directory, &mut output, &mut [0u8; 300]).is_err());
+        assert_eq!(output, &data[..2000]);
+    }
+
+    #[test]
+    fn drop_removes_temporary_files() {
+        let dir = TestDir::new("store-drop");
+        let index = dir.join("index").to_string_lossy().into_owned();
+        let directory = dir.join("store").to_string_lossy().into_owned();
+        let mut store = ChunkStore::new(&directory, &index, true).unwrap();
+        store.write(b"abc").unwrap();
+        store.finish_chunk().unwrap();
+        store.write(b"def").unwrap();
+        assert_eq!(stored_files(&directory).len(), 2);
+        drop(store);
+        // stored chunk is kept, but temporary chunk and index are removed
+        assert_eq!(
+            stored_files(&directory),
+            vec![crate::cdc::sha256_hex(b"abc")]
+        );
+        assert_eq!(dir.file_names(), vec!["store"]);
+    }
+}
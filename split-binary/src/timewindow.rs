@@ -0,0 +1,451 @@
+use super::{
+    get_output_directory, get_text_codec, join_record_lines, open_line_reader, sanitize_file_name,
+    split_line_ending, ArgumentError, Errors, LineOptions, PartitionWriter,
+};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use regex::Regex;
+use std::str::FromStr;
+
+/// max number of parts kept open, only used when out-of-order lines are appended
+const MAX_OPEN_FILES: usize = 16;
+
+/// what to do with a record whose timestamp is earlier than the current window
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub(crate) enum OutOfOrderPolicy {
+    /// keep it in the current part
+    #[default]
+    Current,
+    /// append it to the part of its own window
+    Append,
+    /// stop with an error
+    Error,
+}
+
+impl FromStr for OutOfOrderPolicy {
+    type Err = Errors;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "current" => Ok(OutOfOrderPolicy::Current),
+            "append" => Ok(OutOfOrderPolicy::Append),
+            "error" => Ok(OutOfOrderPolicy::Error),
+            _ => Err(Errors::Arg(ArgumentError::new(
+                "out-of-order",
+                &format!("invalid out-of-order policy:{}", s),
+            ))),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TimeWindow {
+    /// window length in seconds
+    pub window: i64,
+    pub time_format: String,
+    pub time_pattern: Option<Regex>,
+    pub name_format: String,
+    pub out_of_order: OutOfOrderPolicy,
+}
+
+impl TimeWindow {
+    pub fn new(window: i64) -> Self {
+        TimeWindow {
+            window,
+            time_format: "%Y-%m-%dT%H:%M:%S".to_owned(),
+            time_pattern: None,
+            name_format: Self::default_name_format(window).to_owned(),
+            out_of_order: OutOfOrderPolicy::default(),
+        }
+    }
+    pub fn with_time_format(mut self, s: Option<&str>) -> Self {
+        if let Some(s) = s {
+            self.time_format = s.to_owned();
+        }
+        self
+    }
+    pub fn with_time_pattern(mut self, re: Option<Regex>) -> Self {
+        self.time_pattern = re;
+        self
+    }
+    pub fn with_name_format(mut self, s: Option<&str>) -> Self {
+        if let Some(s) = s {
+            self.name_format = s.to_owned();
+        }
+        self
+    }
+    pub fn with_out_of_order(mut self, policy: OutOfOrderPolicy) -> Self {
+        self.out_of_order = policy;
+        self
+    }
+    /// the most coarse name which still distinguishes windows
+    fn default_name_format(window: i64) -> &'static str {
+        if window % 86400 == 0 {
+            "%Y-%m-%d"
+        } else if window % 3600 == 0 {
+            "%Y-%m-%dT%H"
+        } else if window % 60 == 0 {
+            "%Y-%m-%dT%H%M"
+        } else {
+            "%Y-%m-%dT%H%M%S"
+        }
+    }
+    /// parses duration like "90s", "15m", "1h" or "1d"(seconds if unit is omitted)
+    pub fn parse_window(s: &str) -> Result<i64, Errors> {
+        let (num, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+            Some(pos) => s.split_at(pos),
+            None => (s, "s"),
+        };
+        let scale = match unit {
+            "s" => 1,
+            "m" => 60,
+            "h" => 3600,
+            "d" => 86400,
+            _ => 0,
+        };
+        match num.parse::<i64>() {
+            Ok(v) if v > 0 && scale > 0 => v.checked_mul(scale).ok_or_else(|| {
+                Errors::Arg(ArgumentError::new(
+                    "time-window",
+                    "time window is too large",
+                ))
+            }),
+            _ => Err(Errors::Arg(ArgumentError::new(
+                "time-window",
+                &format!("invalid time window:{}", s),
+            ))),
+        }
+    }
+    /// checks strftime format so that formatting never fails later
+    pub fn check_format(s: &str, name: &str) -> Result<(), Errors> {
+        if StrftimeItems::new(s).any(|item| item == Item::Error) {
+            return Err(Errors::Arg(ArgumentError::new(
+                name,
+                &format!("invalid time format:{}", s),
+            )));
+        }
+        Ok(())
+    }
+    /// timestamp is parsed from the first capture group(or whole match) of time pattern,
+    /// or from the beginning of the line if time pattern is not given.
+    /// date only format is treated as midnight.
+    fn parse_timestamp(&self, line: &str) -> Option<NaiveDateTime> {
+        let s = match &self.time_pattern {
+            Some(re) => {
+                let caps = re.captures(line)?;
+                caps.get(1).or_else(|| caps.get(0))?.as_str()
+            }
+            None => line,
+        };
+        match NaiveDateTime::parse_and_remainder(s, &self.time_format) {
+            Ok((v, _)) => Some(v),
+            Err(_) => NaiveDate::parse_and_remainder(s, &self.time_format)
+                .ok()
+                .and_then(|(v, _)| v.and_hms_opt(0, 0, 0)),
+        }
+    }
+    /// windows are aligned to 1970-01-01T00:00:00
+    fn window_start(&self, timestamp: NaiveDateTime) -> NaiveDateTime {
+        let secs = timestamp.and_utc().timestamp();
+        DateTime::from_timestamp(secs - secs.rem_euclid(self.window), 0)
+            .map(|v| v.naive_utc())
+            .unwrap_or(timestamp)
+    }
+    fn window_name(&self, window_start: NaiveDateTime) -> String {
+        sanitize_file_name(&window_start.format(&self.name_format).to_string())
+    }
+}
+
+/// routes records to parts by their time window
+struct TimeWindowWriter<'a> {
+    time_window: &'a TimeWindow,
+    writer: PartitionWriter,
+    current: Option<NaiveDateTime>,
+    /// records before the first timestamp, written to the first part
+    pending: String,
+}
+
+impl<'a> TimeWindowWriter<'a> {
+    fn write_record(
+        &mut self,
+        record: &str,
+        timestamp: Option<NaiveDateTime>,
+        line_number: u64,
+    ) -> Result<(), Errors> {
+        let window_start = match (
+            timestamp.map(|v| self.time_window.window_start(v)),
+            self.current,
+        ) {
+            (None, None) => {
+                self.pending.push_str(record);
+                return Ok(());
+            }
+            (None, Some(current)) => current,
+            (Some(v), Some(current)) if v < current => match self.time_window.out_of_order {
+                OutOfOrderPolicy::Current => current,
+                OutOfOrderPolicy::Append => v,
+                OutOfOrderPolicy::Error => {
+                    return Err(Errors::Io(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!(
+                            "line {}: timestamp {} is earlier than current window {}",
+                            line_number,
+                            timestamp.unwrap_or(v),
+                            current
+                        ),
+                    )))
+                }
+            },
+            (Some(v), _) => {
+                self.current = Some(v);
+                v
+            }
+        };
+        let key = self.time_window.window_name(window_start);
+        if !self.pending.is_empty() {
+            let pending = std::mem::take(&mut self.pending);
+            self.writer.write_record(&key, &pending)?;
+        }
+        self.writer.write_record(&key, record)
+    }
+    /// records without any timestamp are written to "unknown" part
+    fn finish(&mut self) -> Result<(), Errors> {
+        if !self.pending.is_empty() {
+            let pending = std::mem::take(&mut self.pending);
+            self.writer.write_record("unknown", &pending)?;
+        }
+        self.writer.finish()
+    }
+}
+
+/// splits text into parts by time window of timestamps.
+/// a line with timestamp starts a record(or a line matching record-start if it is given),
+/// and other lines are continuation of the previous record.
+pub fn split_text_by_time(opts: &LineOptions, time_window: &TimeWindow) -> Result<(), Errors> {
+    let (decoder, encoder) = get_text_codec(&opts.encoding)?;
+    let mut reader = open_line_reader(opts, decoder)?;
+    let mut writer = TimeWindowWriter {
+        time_window,
+        writer: PartitionWriter::new(
+            get_output_directory(&opts.output)?,
+            opts.prefix.as_deref(),
+            opts.extra_suffix.as_deref(),
+            encoder,
+            MAX_OPEN_FILES,
+        ),
+        current: None,
+        pending: String::new(),
+    };
+    let mut headerlines = 0u64;
+    let mut line_number = 0u64;
+    let mut record_lines: Vec<(String, bool)> = Vec::new();
+    let mut record_timestamp: Option<NaiveDateTime> = None;
+    let mut record_line_number = 0u64;
+    while let Some(lines) = reader.read_lines()? {
+        for (line, is_last_newline) in lines {
+            line_number += 1;
+            if headerlines < opts.header_lines {
+                writer.writer.write_header(&line)?;
+                if is_last_newline {
+                    headerlines += 1;
+                }
+                continue;
+            }
+            let text = split_line_ending(&line).0;
+            let (is_record_start, timestamp) = match &opts.record_start {
+                Some(re) if re.is_match(text) => (true, time_window.parse_timestamp(text)),
+                Some(_) => (false, None),
+                None => {
+                    let timestamp = time_window.parse_timestamp(text);
+                    (timestamp.is_some(), timestamp)
+                }
+            };
+            if is_record_start {
+                if !record_lines.is_empty() {
+                    let record = join_record_lines(std::mem::take(&mut record_lines), opts);
+                    writer.write_record(&record, record_timestamp, record_line_number)?;
+                }
+                record_timestamp = timestamp;
+                record_line_number = line_number;
+            }
+            record_lines.push((line, is_last_newline));
+        }
+    }
+    if !record_lines.is_empty() {
+        let record = join_record_lines(record_lines, opts);
+        writer.write_record(&record, record_timestamp, record_line_number)?;
+    }
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestDir;
+
+    fn datetime(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn parse_window_units() {
+        assert_eq!(TimeWindow::parse_window("90").unwrap(), 90);
+        assert_eq!(TimeWindow::parse_window("90s").unwrap(), 90);
+        assert_eq!(TimeWindow::parse_window("15m").unwrap(), 900);
+        assert_eq!(TimeWindow::parse_window("1h").unwrap(), 3600);
+        assert_eq!(TimeWindow::parse_window("2d").unwrap(), 172800);
+    }
+
+    #[test]
+    fn parse_window_invalid() {
+        for s in &["0", "0h", "", "h", "-1m", "1w", "1 h", "1hh", "1.5h"] {
+            assert!(TimeWindow::parse_window(s).is_err(), "{}", s);
+        }
+        // larger than i64 before and after scaling
+        assert!(TimeWindow::parse_window("9223372036854775808").is_err());
+        assert!(TimeWindow::parse_window("9223372036854775807d").is_err());
+    }
+
+    #[test]
+    fn window_start_before_1970() {
+        let hourly = TimeWindow::new(3600);
+        assert_eq!(
+            hourly.window_start(datetime("1969-12-31T23:30:00")),
+            datetime("1969-12-31T23:00:00")
+        );
+        assert_eq!(
+            hourly.window_start(datetime("1969-12-31T23:00:00")),
+            datetime("1969-12-31T23:00:00")
+        );
+        let daily = TimeWindow::new(86400);
+        assert_eq!(
+            daily.window_start(datetime("1969-07-20T20:17:40")),
+            datetime("1969-07-20T00:00:00")
+        );
+        // window is aligned to 1970-01-01 even if a day is not a multiple of it
+        let seven_hours = TimeWindow::new(7 * 3600);
+        assert_eq!(
+            seven_hours.window_start(datetime("1969-12-31T18:00:00")),
+            datetime("1969-12-31T17:00:00")
+        );
+        assert_eq!(
+            seven_hours.window_start(datetime("1970-01-01T06:59:59")),
+            datetime("1970-01-01T00:00:00")
+        );
+    }
+
+    #[test]
+    fn parse_timestamp_formats() {
+        let time_window = TimeWindow::new(3600);
+        assert_eq!(
+            time_window.parse_timestamp("2024-01-02T03:04:05 message"),
+            Some(datetime("2024-01-02T03:04:05"))
+        );
+        assert_eq!(
+            time_window.parse_timestamp("  at 2024-01-02T03:04:05"),
+            None
+        );
+        let date_only = TimeWindow::new(86400).with_time_format(Some("%Y/%m/%d"));
+        assert_eq!(
+            date_only.parse_timestamp("2024/01/02 message"),
+            Some(datetime("2024-01-02T00:00:00"))
+        );
+        assert_eq!(date_only.parse_timestamp("2024/13/02 message"), None);
+        let with_pattern = TimeWindow::new(86400)
+            .with_time_format(Some("%d/%m/%Y"))
+            .with_time_pattern(Some(Regex::new(r"\[([^\]]+)\]").unwrap()));
+        assert_eq!(
+            with_pattern.parse_timestamp("host [02/01/2024] message"),
+            Some(datetime("2024-01-02T00:00:00"))
+        );
+        assert_eq!(with_pattern.parse_timestamp("host message"), None);
+    }
+
+    /// writes records to hourly parts with the policy, returns the result and contents of parts
+    fn write_records(
+        dir: &TestDir,
+        policy: OutOfOrderPolicy,
+    ) -> (Result<(), Errors>, Vec<(String, String)>) {
+        let time_window = TimeWindow::new(3600).with_out_of_order(policy);
+        let mut writer = TimeWindowWriter {
+            time_window: &time_window,
+            writer: PartitionWriter::new(
+                dir.join(""),
+                None,
+                Some(".log"),
+                get_text_codec(&None).unwrap().1,
+                MAX_OPEN_FILES,
+            ),
+            current: None,
+            pending: String::new(),
+        };
+        let records = [
+            ("first\n", None),
+            ("a\n", Some("2024-01-01T10:10:00")),
+            ("b\n", Some("2024-01-01T11:05:00")),
+            ("c\n", Some("2024-01-01T10:30:00")),
+            ("d\n", None),
+        ];
+        let mut result = Ok(());
+        for (i, (record, timestamp)) in records.iter().enumerate() {
+            result = writer.write_record(record, timestamp.map(datetime), i as u64 + 1);
+            if result.is_err() {
+                break;
+            }
+        }
+        if result.is_ok() {
+            result = writer.finish();
+        }
+        drop(writer);
+        let parts = dir
+            .file_names()
+            .into_iter()
+            .map(|v| {
+                let content = std::fs::read_to_string(dir.join(&v)).unwrap();
+                (v, content)
+            })
+            .collect();
+        (result, parts)
+    }
+
+    #[test]
+    fn out_of_order_current() {
+        let dir = TestDir::new("timewindow-current");
+        let (result, parts) = write_records(&dir, OutOfOrderPolicy::Current);
+        assert!(result.is_ok());
+        assert_eq!(
+            parts,
+            vec![
+                ("2024-01-01T10.log".to_owned(), "first\na\n".to_owned()),
+                ("2024-01-01T11.log".to_owned(), "b\nc\nd\n".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn out_of_order_append() {
+        let dir = TestDir::new("timewindow-append");
+        let (result, parts) = write_records(&dir, OutOfOrderPolicy::Append);
+        assert!(result.is_ok());
+        // records after the out-of-order one stay in the current window
+        assert_eq!(
+            parts,
+            vec![
+                ("2024-01-01T10.log".to_owned(), "first\na\nc\n".to_owned()),
+                ("2024-01-01T11.log".to_owned(), "b\nd\n".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn out_of_order_error() {
+        let dir = TestDir::new("timewindow-error");
+        let (result, _) = write_records(&dir, OutOfOrderPolicy::Error);
+        match result {
+            Err(Errors::Io(e)) => {
+                assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+                assert!(e.to_string().starts_with("line 4:"), "{}", e);
+            }
+            _ => panic!("out-of-order record must be an error"),
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use super::partfile::open_part;
+use super::resume::{commit_part, input_shorter_error, Journal};
+use super::sparse::{copy_hashed, copy_skipping_zeros, copy_sparse};
+use super::{get_output_directory, BinaryOptions, Errors, DEFAULT_BUFFER_SIZE};
+use std::io::{Read, Write};
+
+/// how data was copied between files
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum CopyMethod {
+    CopyFileRange,
+    Sendfile,
+    Buffered,
+    /// nothing was copied since the range has only holes
+    Hole,
+}
+
+impl std::fmt::Display for CopyMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CopyMethod::CopyFileRange => write!(f, "copy_file_range"),
+            CopyMethod::Sendfile => write!(f, "sendfile"),
+            CopyMethod::Buffered => write!(f, "buffered"),
+            CopyMethod::Hole => write!(f, "hole"),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::CopyMethod;
+    use std::os::unix::io::AsRawFd;
+
+    /// errors meaning the syscall can not be used for these files
+    fn is_unsupported(e: &std::io::Error) -> bool {
+        matches!(
+            e.raw_os_error(),
+            Some(libc::EXDEV)
+                | Some(libc::ENOSYS)
+                | Some(libc::EOPNOTSUPP)
+                | Some(libc::EINVAL)
+                | Some(libc::EPERM)
+                | Some(libc::EBADF)
+        )
+    }
+
+    fn copy_with<F>(length: u64, mut f: F) -> std::io::Result<Option<u64>>
+    where
+        F: FnMut(usize) -> isize,
+    {
+        let mut copied = 0u64;
+        while copied < length {
+            // large counts are split since the syscalls copy at most 2GiB at once
+            let count = std::cmp::min(length - copied, 1 << 30) as usize;
+            let ret = f(count);
+            if ret < 0 {
+                let e = std::io::Error::last_os_error();
+                if copied == 0 && is_unsupported(&e) {
+                    return Ok(None);
+                }
+                return Err(e);
+            }
+            if ret == 0 {
+                break;
+            }
+            copied += ret as u64;
+        }
+        Ok(Some(copied))
+    }
+
+    /// copies from current positions of both files in kernel,
+    /// returns None if neither copy_file_range nor sendfile can be used.
+    pub fn copy_range(
+        input: &std::fs::File,
+        output: &std::fs::File,
+        length: u64,
+    ) -> std::io::Result<Option<(u64, CopyMethod)>> {
+        let (fd_in, fd_out) = (input.as_raw_fd(), output.as_raw_fd());
+        let copied = copy_with(length, |count| unsafe {
+            libc::copy_file_range(
+                fd_in,
+                std::ptr::null_mut(),
+                fd_out,
+                std::ptr::null_mut(),
+                count,
+                0,
+            ) as isize
+        })?;
+        if let Some(copied) = copied {
+            return Ok(Some((copied, CopyMethod::CopyFileRange)));
+        }
+        let copied = copy_with(length, |count| unsafe {
+            libc::sendfile(fd_out, fd_in, std::ptr::null_mut(), count) as isize
+        })?;
+        Ok(copied.map(|v| (v, CopyMethod::Sendfile)))
+    }
+}
+
+/// copies up to length bytes from current position of input to output,
+/// in kernel if possible and through buf otherwise.
+/// if is_zero_skipped, blocks of only zeros in buf are seeked over in output.
+pub(crate) fn copy_between_files(
+    input: &mut std::fs::File,
+    output: &mut std::fs::File,
+    length: u64,
+    buf: &mut [u8],
+    is_zero_skipped: bool,
+) -> Result<(u64, CopyMethod), Errors> {
+    #[cfg(target_os = "linux")]
+    {
+        if let Some(v) = linux::copy_range(input, output, length)
+            .map_err(|e| Errors::from_io(&e, "copying file content"))?
+        {
+            return Ok(v);
+        }
+    }
+    if is_zero_skipped {
+        let copied = copy_skipping_zeros(input, output, length, buf, None)?;
+        return Ok((copied, CopyMethod::Buffered));
+    }
+    let mut copied = 0u64;
+    while copied < length {
+        let size = std::cmp::min(buf.len() as u64, length - copied) as usize;
+        let bytesread = input
+            .read(&mut buf[0..size])
+            .map_err(|e| Errors::from_io(&e, "reading from input file"))?;
+        if bytesread == 0 {
+            break;
+        }
+        output
+            .write_all(&buf[0..bytesread])
+            .map_err(|e| Errors::from_io(&e, "writing output file"))?;
+        copied += bytesread as u64;
+    }
+    Ok((copied, CopyMethod::Buffered))
+}
+
+/// splits a regular file part by part, copying in kernel if possible and skipping holes.
+/// parts already in the journal are skipped.
+pub(crate) fn split_binary_file(
+    opts: &BinaryOptions,
+    mut input: std::fs::File,
+    length: u64,
+    mut journal: Option<&mut Journal>,
+) -> Result<(), Errors> {
+    let (mut current_suffix, start) = match &journal {
+        Some(v) if v.is_done || (v.offset > 0 && v.offset == length) => return Ok(()),
+        Some(v) if v.offset > length => return Err(input_shorter_error(v.offset, length)),
+        Some(v) => (v.suffix.clone(), v.offset),
+        None => (String::new(), 0),
+    };
+    let mut buf = vec![0; opts.buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE)];
+    let prefix = opts.prefix.clone().unwrap_or(String::from("x"));
+    let mut output_file_path = get_output_directory(&opts.output)?;
+    output_file_path.push(format!("{}.{}", prefix, ""));
+    let extra_suffix = opts.extra_suffix.clone().unwrap_or_default();
+    let mut remaining = length - start;
+    loop {
+        let mut output_file = open_part(
+            &mut current_suffix,
+            &prefix,
+            &mut output_file_path,
+            opts.is_numerical_suffix,
+            &extra_suffix,
+        )?;
+        if length - remaining != start {
+            eprintln!("next suffix = {}", current_suffix);
+        }
+        let size = std::cmp::min(opts.max_size, remaining);
+        let offset = length - remaining;
+        // holes of input are kept as holes in parts
+        let (copied, method) = match journal.as_deref_mut() {
+            // parts are hashed for the journal while they are copied
+            Some(journal) => (
+                copy_hashed(
+                    &mut input,
+                    &mut output_file,
+                    offset,
+                    offset + size,
+                    &mut buf,
+                    journal.hasher(),
+                )?,
+                CopyMethod::Buffered,
+            ),
+            None => copy_sparse(
+                &mut input,
+                &mut output_file,
+                offset,
+                offset + size,
+                &mut buf,
+                false,
+            )?,
+        };
+        if opts.is_verbose {
+            eprintln!(
+                "{}: {} bytes by {}",
+                output_file_path.display(),
+                copied,
+                method
+            );
+        }
+        commit_part(output_file, copied, opts.is_sync, journal.as_deref_mut())?;
+        remaining -= copied;
+        // input may be truncated while splitting
+        if remaining == 0 || copied < size || size == 0 {
+            break;
+        }
+    }
+    Ok(())
+}